@@ -3,14 +3,56 @@ macro_rules! conf_defaults {
     () => {
         [
             ("link_addr".to_string(), "127.0.0.1".to_string()),
+            ("link_addr6".to_string(), "".to_string()),
             ("link_parent_iface".to_string(), "wlo1".to_string()),
             ("link_iface".to_string(), "lilap0".to_string()),
             ("link_ssid".to_string(), "lilapFree".to_string()),
             ("web_dir".to_string(), "./example/".to_string()),
             ("web_port".to_string(), "80".to_string()),
             ("dns_port".to_string(), "53".to_string()),
+            ("dns_hijack_records".to_string(), "".to_string()),
+            ("dns_upstream".to_string(), "".to_string()),
+            ("dns_upstream_timeout".to_string(), "2000".to_string()),
+            ("dns_tcp_idle_timeout".to_string(), "10000".to_string()),
+            ("dns_walled_garden".to_string(), "".to_string()),
+            ("dns_cache_size".to_string(), "1000".to_string()),
+            ("dns_cache_min_ttl".to_string(), "0".to_string()),
+            ("dns_cache_max_ttl".to_string(), "86400".to_string()),
+            ("dns_zone".to_string(), "".to_string()),
+            ("dns_hosts_file".to_string(), "".to_string()),
+            ("dns_domain".to_string(), "lilap".to_string()),
             ("dhcp_src_port".to_string(), "67".to_string()),
             ("dhcp_dst_port".to_string(), "68".to_string()),
+            ("dhcp_pool_start".to_string(), "127.0.0.100".to_string()),
+            ("dhcp_pool_end".to_string(), "127.0.0.200".to_string()),
+            ("dhcp_subnet_mask".to_string(), "255.255.255.0".to_string()),
+            ("dhcp_lease_time".to_string(), "3600".to_string()),
+            ("dhcp_lease_file".to_string(), "./lilap.leases".to_string()),
+            ("dhcp_server_id".to_string(), "".to_string()),
+            ("dhcp_router".to_string(), "".to_string()),
+            ("dhcp_dns_server".to_string(), "".to_string()),
+            ("dhcp_domain".to_string(), "".to_string()),
+            ("dhcp_renewal_time".to_string(), "".to_string()),
+            ("dhcp_rebinding_time".to_string(), "".to_string()),
+            ("dhcp_captive_portal_uri".to_string(), "".to_string()),
+            ("dhcp_reservations".to_string(), "".to_string()),
+            ("dhcp_probe_timeout".to_string(), "250".to_string()),
+            ("dhcp_relay_pools".to_string(), "".to_string()),
+            ("dhcp_scopes".to_string(), "".to_string()),
+            ("dhcp_script".to_string(), "".to_string()),
+            ("dhcp_script_timeout".to_string(), "10".to_string()),
+            ("dhcp_allow_file".to_string(), "".to_string()),
+            ("dhcp_deny_file".to_string(), "".to_string()),
+            ("dhcp6_port".to_string(), "547".to_string()),
+            ("dhcp6_pool_start".to_string(), "".to_string()),
+            ("dhcp6_pool_end".to_string(), "".to_string()),
+            ("dhcp6_lease_time".to_string(), "3600".to_string()),
+            ("dhcp6_dns_server".to_string(), "".to_string()),
+            ("ra_prefix".to_string(), "".to_string()),
+            ("ra_interval".to_string(), "600".to_string()),
+            ("ra_router_lifetime".to_string(), "1800".to_string()),
+            ("ra_valid_lifetime".to_string(), "86400".to_string()),
+            ("ra_preferred_lifetime".to_string(), "14400".to_string()),
         ]
     };
 }
@@ -11,7 +11,9 @@ use std::env;
 use server::web::Web;
 use server::dns::Dns;
 use server::dhcp::Dhcp;
+use server::dhcp6::Dhcp6;
 use server::link::Link;
+use server::ra::Ra;
 
 fn main() {
     let mut conf = Conf::from(conf_defaults!());
@@ -41,16 +43,20 @@ fn main() {
     let web_server = ServerFactory::create::<Web>(&conf);
     let dns_server = ServerFactory::create::<Dns>(&conf);
     let dhcp_server = ServerFactory::create::<Dhcp>(&conf);
+    let dhcp6_server = ServerFactory::create::<Dhcp6>(&conf);
     let link_server = ServerFactory::create::<Link>(&conf);
+    let ra_server = ServerFactory::create::<Ra>(&conf);
 
     let mut signals =
-        Signals::new(&[SIGINT, SIGABRT, SIGTERM]).expect("Error setting up signal handler");
+        Signals::new([SIGINT, SIGABRT, SIGTERM]).expect("Error setting up signal handler");
     signals.wait();
 
     web_server.destroy();
     dns_server.destroy();
     dhcp_server.destroy();
+    dhcp6_server.destroy();
     link_server.destroy();
+    ra_server.destroy();
 
     ServerFactory::join();
 }
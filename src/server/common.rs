@@ -2,7 +2,9 @@ use crate::server::*;
 use crate::server::web::*;
 use crate::server::dns::*;
 use crate::server::dhcp::*;
+use crate::server::dhcp6::*;
 use crate::server::link::*;
+use crate::server::ra::*;
 
 impl HasStateField for Web {
     fn state(&self) -> &ServerState {
@@ -31,6 +33,15 @@ impl HasStateField for Dhcp {
     }
 }
 
+impl HasStateField for Dhcp6 {
+    fn state(&self) -> &ServerState {
+        &self.state
+    }
+    fn state_mut(&mut self) -> &mut ServerState {
+        &mut self.state
+    }
+}
+
 impl HasStateField for Link {
     fn state(&self) -> &ServerState {
         &self.state
@@ -38,4 +49,13 @@ impl HasStateField for Link {
     fn state_mut(&mut self) -> &mut ServerState {
         &mut self.state
     }
+}
+
+impl HasStateField for Ra {
+    fn state(&self) -> &ServerState {
+        &self.state
+    }
+    fn state_mut(&mut self) -> &mut ServerState {
+        &mut self.state
+    }
 }
\ No newline at end of file
@@ -0,0 +1,66 @@
+use crate::server::events::LeaseEvent;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// External program told about every lease change, like dnsmasq's `--dhcp-script`.
+///
+/// It is run as `<path> <action> <mac> <addr> [hostname]`, where action is
+/// `add` for a new lease, `old` for a renewed one and `del` for a lease that
+/// was released or expired. Programs still running after the timeout are
+/// killed.
+pub struct Hook {
+    path: String,
+    timeout: Duration,
+}
+
+impl Hook {
+    pub fn new(path: &str, timeout: Duration) -> Self {
+        Hook {
+            path: path.to_string(),
+            timeout,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn action(event: &LeaseEvent) -> &'static str {
+        match event {
+            LeaseEvent::Granted(_) => "add",
+            LeaseEvent::Renewed(_) => "old",
+            LeaseEvent::Released(_) | LeaseEvent::Expired(_) => "del",
+        }
+    }
+
+    /// Runs the program for an event and waits for it to finish
+    pub fn run(&self, event: &LeaseEvent) -> Result<(), String> {
+        let client = event.client();
+        let mut command = Command::new(&self.path);
+        command
+            .arg(Self::action(event))
+            .arg(client.mac.to_string())
+            .arg(client.addr.to_string())
+            .stdin(Stdio::null());
+        if let Some(hostname) = &client.info.hostname {
+            command.arg(hostname);
+        }
+
+        let mut child = command.spawn().map_err(|e| format!("could not start: {}", e))?;
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) if status.success() => return Ok(()),
+                Ok(Some(status)) => return Err(format!("exited with {}", status)),
+                Ok(None) if Instant::now() >= deadline => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(format!("killed after {}s", self.timeout.as_secs()));
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(10)),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+}
@@ -0,0 +1,449 @@
+use crate::server::iface::MacAddr;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// How long an offered address is held for a client that has not requested it yet
+const OFFER_HOLD_TIME: u64 = 60;
+// How long a declined address is kept out of the pool
+const DECLINE_HOLD_TIME: u64 = 600;
+
+/// Fixed address for a MAC, written as `<mac> <addr> [hostname]` in the config
+#[derive(Clone, Debug)]
+pub struct Reservation {
+    pub mac: MacAddr,
+    pub addr: Ipv4Addr,
+    pub hostname: Option<String>,
+}
+
+impl FromStr for Reservation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() < 2 || fields.len() > 3 {
+            return Err(format!("Invalid reservation: {}", s));
+        }
+        Ok(Reservation {
+            mac: fields[0].parse()?,
+            addr: fields[1].parse().map_err(|_| format!("Invalid reservation address: {}", fields[1]))?,
+            hostname: fields.get(2).map(|hostname| hostname.to_string()),
+        })
+    }
+}
+
+/// What a client said about itself in its last DISCOVER or REQUEST
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientInfo {
+    /// Option 12
+    pub hostname: Option<String>,
+    /// Option 60
+    pub vendor_class: Option<String>,
+    /// Option 55, the option codes the client asked for
+    pub parameters: Vec<u8>,
+}
+
+impl ClientInfo {
+    /// Names the client for logs, e.g. `Pixel-7 (aa:bb:cc:dd:ee:ff)`
+    pub fn label(&self, mac: &MacAddr) -> String {
+        match &self.hostname {
+            Some(hostname) => format!("{} ({})", hostname, mac),
+            None => mac.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LeaseState {
+    Offered,
+    Bound,
+    Expired,
+}
+
+#[derive(Clone, Debug)]
+pub struct Lease {
+    pub client_id: Vec<u8>,
+    pub mac: MacAddr,
+    pub addr: Ipv4Addr,
+    pub expires: u64,
+    pub state: LeaseState,
+    pub info: ClientInfo,
+}
+
+/// Leases handed out from a single address pool, keyed by client identifier.
+///
+/// Clients that do not send option 61 are identified by their hardware type
+/// and address, the way most clients build option 61 themselves. Expired
+/// leases are kept around so a returning client gets its previous address
+/// back, and are only recycled once the pool has no never-used address left.
+///
+/// Reserved addresses are only ever given to the MAC they are reserved for,
+/// whether they lie inside the pool or not.
+pub struct LeaseTable {
+    pool_start: u32,
+    pool_end: u32,
+    excluded: Vec<Ipv4Addr>,
+    reservations: Vec<Reservation>,
+    lease_time: u32,
+    leases: HashMap<Vec<u8>, Lease>,
+    declined: HashMap<Ipv4Addr, u64>,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl LeaseTable {
+    pub fn new(pool_start: Ipv4Addr, pool_end: Ipv4Addr, excluded: Vec<Ipv4Addr>, lease_time: u32) -> Self {
+        LeaseTable {
+            pool_start: u32::from(pool_start),
+            pool_end: u32::from(pool_end),
+            excluded,
+            reservations: Vec::new(),
+            lease_time,
+            leases: HashMap::new(),
+            declined: HashMap::new(),
+        }
+    }
+
+    pub fn add_reservation(&mut self, reservation: Reservation) -> Result<(), String> {
+        if self.excluded.contains(&reservation.addr) {
+            return Err(format!("{} cannot be handed out", reservation.addr));
+        }
+        if let Some(existing) = self.reservations.iter().find(|r| r.mac == reservation.mac || r.addr == reservation.addr) {
+            return Err(format!("{} conflicts with the reservation of {} for {}", reservation.addr, existing.addr, existing.mac));
+        }
+        self.reservations.push(reservation);
+        Ok(())
+    }
+
+    pub fn reservation(&self, mac: &MacAddr) -> Option<&Reservation> {
+        self.reservations.iter().find(|reservation| reservation.mac == *mac)
+    }
+
+    fn is_reserved(&self, addr: Ipv4Addr) -> bool {
+        self.reservations.iter().any(|reservation| reservation.addr == addr)
+    }
+
+    pub fn lease_time(&self) -> u32 {
+        self.lease_time
+    }
+
+    pub fn pool_size(&self) -> u32 {
+        self.pool_end.saturating_sub(self.pool_start) + 1
+    }
+
+    pub fn in_pool(&self, addr: Ipv4Addr) -> bool {
+        let addr_u32 = u32::from(addr);
+        addr_u32 >= self.pool_start && addr_u32 <= self.pool_end && !self.excluded.contains(&addr)
+    }
+
+    fn is_declined(&self, addr: Ipv4Addr) -> bool {
+        self.declined.get(&addr).is_some_and(|until| *until > now())
+    }
+
+    fn owner(&self, addr: Ipv4Addr) -> Option<&Lease> {
+        self.leases.values().find(|lease| lease.addr == addr)
+    }
+
+    /// Whether `addr` can be given to `client_id` without taking it from anyone else
+    fn is_free_for(&self, addr: Ipv4Addr, client_id: &[u8], mac: &MacAddr) -> bool {
+        if let Some(reservation) = self.reservation(mac) {
+            return addr == reservation.addr;
+        }
+        self.in_pool(addr)
+            && !self.is_declined(addr)
+            && !self.is_reserved(addr)
+            && self.owner(addr).is_none_or(|lease| lease.client_id == client_id)
+    }
+
+    /// Takes back a lease read from the lease file, unless its address is no longer ours to give
+    pub fn restore(&mut self, lease: Lease) -> Result<(), String> {
+        if !self.is_free_for(lease.addr, &lease.client_id, &lease.mac) {
+            return Err(format!("{} is outside the pool, reserved or already leased", lease.addr));
+        }
+        self.leases.insert(lease.client_id.clone(), lease);
+        Ok(())
+    }
+
+    /// Address the client holds or held last, if any
+    pub fn address_of(&self, client_id: &[u8]) -> Option<Ipv4Addr> {
+        self.leases.get(client_id).map(|lease| lease.addr)
+    }
+
+    pub fn is_bound(&self, client_id: &[u8], addr: Ipv4Addr) -> bool {
+        self.leases.get(client_id).is_some_and(|lease| lease.addr == addr && lease.state == LeaseState::Bound)
+    }
+
+    pub fn bound(&self) -> impl Iterator<Item = &Lease> {
+        self.leases.values().filter(|lease| lease.state == LeaseState::Bound)
+    }
+
+    /// Picks an address for a DISCOVER and holds it for a short while
+    pub fn offer(&mut self, client_id: &[u8], mac: MacAddr, requested: Option<Ipv4Addr>, info: &ClientInfo) -> Option<Ipv4Addr> {
+        let addr = match (self.reservation(&mac), self.leases.get(client_id)) {
+            (Some(reservation), _) => reservation.addr,
+            (None, Some(lease)) if self.is_free_for(lease.addr, client_id, &mac) => lease.addr,
+            _ => match requested {
+                Some(addr) if self.is_free_for(addr, client_id, &mac) => addr,
+                _ => self.next_free()?,
+            },
+        };
+
+        // Recycling an expired lease of another client, or claiming a reserved address, takes it away from them
+        self.leases.retain(|id, lease| id.as_slice() == client_id || lease.addr != addr);
+
+        let lease = self.leases.entry(client_id.to_vec()).or_insert(Lease {
+            client_id: client_id.to_vec(),
+            mac,
+            addr,
+            expires: 0,
+            state: LeaseState::Offered,
+            info: ClientInfo::default(),
+        });
+        lease.mac = mac;
+        lease.addr = addr;
+        lease.info = info.clone();
+        if lease.state != LeaseState::Bound {
+            lease.state = LeaseState::Offered;
+            lease.expires = now() + OFFER_HOLD_TIME;
+        }
+        Some(addr)
+    }
+
+    fn next_free(&self) -> Option<Ipv4Addr> {
+        let unused = (self.pool_start..=self.pool_end)
+            .map(Ipv4Addr::from)
+            .find(|addr| self.in_pool(*addr) && !self.is_declined(*addr) && !self.is_reserved(*addr) && self.owner(*addr).is_none());
+        if unused.is_some() {
+            return unused;
+        }
+
+        // Pool exhausted, recycle the address that expired the longest time ago
+        self.leases
+            .values()
+            .filter(|lease| {
+                lease.state == LeaseState::Expired
+                    && self.in_pool(lease.addr)
+                    && !self.is_declined(lease.addr)
+                    && !self.is_reserved(lease.addr)
+            })
+            .min_by_key(|lease| lease.expires)
+            .map(|lease| lease.addr)
+    }
+
+    /// Binds or renews `addr` for the client, returns the new lease or None if the address is not available to it
+    pub fn bind(&mut self, client_id: &[u8], mac: MacAddr, addr: Ipv4Addr, info: ClientInfo) -> Option<Lease> {
+        if !self.is_free_for(addr, client_id, &mac) {
+            return None;
+        }
+
+        self.leases.retain(|id, lease| id.as_slice() == client_id || lease.addr != addr);
+        let lease = Lease {
+            client_id: client_id.to_vec(),
+            mac,
+            addr,
+            expires: now() + self.lease_time as u64,
+            state: LeaseState::Bound,
+            info,
+        };
+        self.leases.insert(client_id.to_vec(), lease.clone());
+        Some(lease)
+    }
+
+    /// Drops an offer the client did not take, bound leases are left untouched
+    pub fn forget_offer(&mut self, client_id: &[u8]) {
+        if self.leases.get(client_id).is_some_and(|lease| lease.state == LeaseState::Offered) {
+            self.leases.remove(client_id);
+        }
+    }
+
+    /// Ends the client's lease early but remembers the address for it, returns the lease as it was before
+    pub fn release(&mut self, client_id: &[u8], addr: Ipv4Addr) -> Option<Lease> {
+        match self.leases.get_mut(client_id) {
+            Some(lease) if lease.addr == addr && lease.state == LeaseState::Bound => {
+                let released = lease.clone();
+                lease.state = LeaseState::Expired;
+                lease.expires = now();
+                Some(released)
+            }
+            _ => None,
+        }
+    }
+
+    /// Takes an address the client found to be in use out of the pool for a while, returns the client's lease on it
+    pub fn decline(&mut self, client_id: &[u8], addr: Ipv4Addr) -> Option<Lease> {
+        let lease = match self.leases.get(client_id) {
+            Some(lease) if lease.addr == addr => self.leases.remove(client_id),
+            _ => None,
+        };
+        self.mark_in_use(addr);
+        lease
+    }
+
+    /// Takes an address someone on the segment already uses out of the pool for a while
+    pub fn mark_in_use(&mut self, addr: Ipv4Addr) {
+        self.leases.retain(|_, lease| lease.addr != addr || lease.state == LeaseState::Bound);
+        self.declined.insert(addr, now() + DECLINE_HOLD_TIME);
+    }
+
+    /// Expires every lease whose time is up and returns the bound ones that just lapsed
+    pub fn expire(&mut self) -> Vec<Lease> {
+        let now = now();
+        let mut expired = Vec::new();
+
+        self.leases.retain(|_, lease| !(lease.state == LeaseState::Offered && lease.expires <= now));
+        for lease in self.leases.values_mut() {
+            if lease.state == LeaseState::Bound && lease.expires <= now {
+                lease.state = LeaseState::Expired;
+                expired.push(lease.clone());
+            }
+        }
+        self.declined.retain(|_, until| *until > now);
+
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> Ipv4Addr {
+        s.parse().unwrap()
+    }
+
+    fn client(n: u8) -> (Vec<u8>, MacAddr) {
+        let mac = MacAddr([0xaa, 0xbb, 0xcc, 0, 0, n]);
+        let mut client_id = vec![1];
+        client_id.extend(mac.0);
+        (client_id, mac)
+    }
+
+    fn table() -> LeaseTable {
+        LeaseTable::new(addr("10.0.0.10"), addr("10.0.0.12"), vec![addr("10.0.0.11")], 3600)
+    }
+
+    // Moves the client's lease to the past, as if its time ran out
+    fn lapse(table: &mut LeaseTable, client_id: &[u8]) {
+        table.leases.get_mut(client_id).unwrap().expires = now() - 1;
+    }
+
+    #[test]
+    fn offer_bind_renew() {
+        let mut table = table();
+        let (id, mac) = client(1);
+        let offered = table.offer(&id, mac, None, &ClientInfo::default()).unwrap();
+        assert_eq!(offered, addr("10.0.0.10"));
+        assert!(!table.is_bound(&id, offered));
+
+        let lease = table.bind(&id, mac, offered, ClientInfo::default()).unwrap();
+        assert_eq!(lease.state, LeaseState::Bound);
+        assert!(table.is_bound(&id, offered));
+
+        lapse(&mut table, &id);
+        let renewed = table.bind(&id, mac, offered, ClientInfo::default()).unwrap();
+        assert!(renewed.expires >= now() + 3600 - 1);
+        assert!(table.expire().is_empty());
+        assert_eq!(table.bound().count(), 1);
+    }
+
+    #[test]
+    fn same_address_for_the_same_client() {
+        let mut table = table();
+        let (id1, mac1) = client(1);
+        let (id2, mac2) = client(2);
+        let first = table.offer(&id1, mac1, None, &ClientInfo::default()).unwrap();
+        table.bind(&id1, mac1, first, ClientInfo::default()).unwrap();
+        let released = table.release(&id1, first).unwrap();
+        assert_eq!(released.state, LeaseState::Bound);
+
+        // Someone else gets another address while the old one is remembered
+        assert_eq!(table.offer(&id2, mac2, Some(first), &ClientInfo::default()), Some(addr("10.0.0.12")));
+        assert_eq!(table.offer(&id1, mac1, None, &ClientInfo::default()), Some(first));
+        assert_eq!(table.address_of(&id1), Some(first));
+    }
+
+    #[test]
+    fn offers_expire() {
+        let mut table = table();
+        let (id, mac) = client(1);
+        table.offer(&id, mac, None, &ClientInfo::default()).unwrap();
+        lapse(&mut table, &id);
+        assert!(table.expire().is_empty());
+        assert_eq!(table.address_of(&id), None);
+
+        // Only bound leases lapse into expired ones
+        let offered = table.offer(&id, mac, None, &ClientInfo::default()).unwrap();
+        table.bind(&id, mac, offered, ClientInfo::default()).unwrap();
+        lapse(&mut table, &id);
+        let expired = table.expire();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].addr, offered);
+        assert_eq!(table.bound().count(), 0);
+        assert_eq!(table.address_of(&id), Some(offered));
+    }
+
+    #[test]
+    fn reservations() {
+        let mut table = table();
+        let (id1, mac1) = client(1);
+        let (id2, mac2) = client(2);
+        table
+            .add_reservation(Reservation {
+                mac: mac1,
+                addr: addr("10.0.0.10"),
+                hostname: None,
+            })
+            .unwrap();
+        assert!(table.add_reservation("aa:bb:cc:00:00:03 10.0.0.10".parse().unwrap()).is_err());
+        assert!(table.add_reservation("aa:bb:cc:00:00:03 10.0.0.11".parse().unwrap()).is_err());
+
+        assert_eq!(table.offer(&id2, mac2, Some(addr("10.0.0.10")), &ClientInfo::default()), Some(addr("10.0.0.12")));
+        assert!(table.bind(&id2, mac2, addr("10.0.0.10"), ClientInfo::default()).is_none());
+        assert_eq!(table.offer(&id1, mac1, Some(addr("10.0.0.12")), &ClientInfo::default()), Some(addr("10.0.0.10")));
+        assert!(table.bind(&id1, mac1, addr("10.0.0.12"), ClientInfo::default()).is_none());
+        assert!(table.bind(&id1, mac1, addr("10.0.0.10"), ClientInfo::default()).is_some());
+    }
+
+    #[test]
+    fn declined_addresses_are_held_back() {
+        let mut table = table();
+        let (id1, mac1) = client(1);
+        let (id2, mac2) = client(2);
+        let offered = table.offer(&id1, mac1, None, &ClientInfo::default()).unwrap();
+        table.bind(&id1, mac1, offered, ClientInfo::default()).unwrap();
+        assert!(table.decline(&id1, offered).is_some());
+        assert_eq!(table.address_of(&id1), None);
+
+        assert_eq!(table.offer(&id2, mac2, Some(offered), &ClientInfo::default()), Some(addr("10.0.0.12")));
+        assert!(table.bind(&id2, mac2, offered, ClientInfo::default()).is_none());
+
+        table.declined.insert(offered, now());
+        table.expire();
+        assert!(table.bind(&id2, mac2, offered, ClientInfo::default()).is_some());
+    }
+
+    #[test]
+    fn expired_leases_are_recycled_last() {
+        let mut table = table();
+        let (id1, mac1) = client(1);
+        let (id2, mac2) = client(2);
+        let (id3, mac3) = client(3);
+        let first = table.offer(&id1, mac1, None, &ClientInfo::default()).unwrap();
+        table.bind(&id1, mac1, first, ClientInfo::default()).unwrap();
+        lapse(&mut table, &id1);
+        table.expire();
+
+        // A never-used address goes first, the expired lease only once there is none left
+        let second = table.offer(&id2, mac2, None, &ClientInfo::default()).unwrap();
+        assert_ne!(second, first);
+        table.bind(&id2, mac2, second, ClientInfo::default()).unwrap();
+        assert_eq!(table.offer(&id3, mac3, None, &ClientInfo::default()), Some(first));
+        assert_eq!(table.address_of(&id1), None);
+    }
+}
@@ -0,0 +1,268 @@
+use crate::server::dhcp::lease::{now, ClientInfo, Lease, LeaseState};
+use crate::server::iface::MacAddr;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::Ipv4Addr;
+
+/// On-disk copy of the bound leases, so clients keep their address across restarts.
+///
+/// The file holds one lease per line, fields separated by a single space:
+///
+/// ```text
+/// <expiry> <mac> <addr> <client-id> [<hostname> <vendor-class> <parameters>]
+/// 1718031234 aa:bb:cc:dd:ee:ff 192.168.4.100 01aabbccddeeff Pixel-7 616e64726f6964 0103060f
+/// ```
+///
+/// `expiry` is in seconds since the Unix epoch and `client-id` is the hex
+/// encoded option 61 value. The optional fields hold what the client sent in
+/// options 12, 60 and 55, the latter two hex encoded, with `-` standing for
+/// an option the client did not send. Empty lines and lines starting with
+/// `#` are ignored. The file is rewritten as a whole on every change, by writing a
+/// temporary file next to it and renaming it over the old one.
+pub struct LeaseFile {
+    path: String,
+}
+
+impl LeaseFile {
+    pub fn new(path: &str) -> Self {
+        LeaseFile {
+            path: path.to_string(),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Reads back the leases that are still running, along with a description of every line that had to be skipped
+    pub fn load(&self) -> io::Result<(Vec<Lease>, Vec<String>)> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(), Vec::new())),
+            Err(e) => return Err(e),
+        };
+
+        let now = now();
+        let mut leases = Vec::new();
+        let mut skipped = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match Self::parse_line(line) {
+                Ok(lease) if lease.expires > now => leases.push(lease),
+                Ok(_) => {}
+                Err(e) => skipped.push(format!("line {}: {}", i + 1, e)),
+            }
+        }
+        Ok((leases, skipped))
+    }
+
+    fn parse_line(line: &str) -> Result<Lease, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err(format!("expected 4 fields, found {}", fields.len()));
+        }
+
+        let expires = fields[0].parse::<u64>().map_err(|_| format!("invalid expiry {}", fields[0]))?;
+        let mac = fields[1].parse::<MacAddr>()?;
+        let addr = fields[2].parse::<Ipv4Addr>().map_err(|_| format!("invalid address {}", fields[2]))?;
+        let client_id = Self::decode_hex(fields[3]).ok_or_else(|| format!("invalid client id {}", fields[3]))?;
+
+        let field = |i: usize| fields.get(i).copied().filter(|field| *field != "-");
+        let vendor_class = match field(5) {
+            Some(hex) => Some(Self::decode_hex(hex).ok_or_else(|| format!("invalid vendor class {}", hex))?),
+            None => None,
+        };
+        let parameters = match field(6) {
+            Some(hex) => Self::decode_hex(hex).ok_or_else(|| format!("invalid parameter list {}", hex))?,
+            None => Vec::new(),
+        };
+        let info = ClientInfo {
+            hostname: field(4).map(str::to_string),
+            vendor_class: vendor_class.map(|bytes| String::from_utf8_lossy(&bytes).into_owned()),
+            parameters,
+        };
+
+        Ok(Lease {
+            client_id,
+            mac,
+            addr,
+            expires,
+            state: LeaseState::Bound,
+            info,
+        })
+    }
+
+    /// Atomically replaces the file with the given leases
+    pub fn save<'a>(&self, leases: impl Iterator<Item = &'a Lease>) -> io::Result<()> {
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut file = File::create(&tmp_path)?;
+        for lease in leases {
+            let info = &lease.info;
+            writeln!(
+                file,
+                "{} {} {} {} {} {} {}",
+                lease.expires,
+                lease.mac,
+                lease.addr,
+                Self::encode_hex(&lease.client_id),
+                info.hostname.as_deref().unwrap_or("-"),
+                Self::encode_optional(info.vendor_class.as_deref().unwrap_or("").as_bytes()),
+                Self::encode_optional(&info.parameters),
+            )?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    fn encode_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn encode_optional(bytes: &[u8]) -> String {
+        if bytes.is_empty() {
+            "-".to_string()
+        } else {
+            Self::encode_hex(bytes)
+        }
+    }
+
+    fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+        if hex.is_empty() || !hex.len().is_multiple_of(2) {
+            return None;
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // A lease file path of its own for every test, removed on drop
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("lilap-{}-{}", std::process::id(), name));
+            let _ = fs::remove_file(&path);
+            TempFile(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn lease(n: u8, expires: u64, info: ClientInfo) -> Lease {
+        let mac = MacAddr([0xaa, 0xbb, 0xcc, 0, 0, n]);
+        Lease {
+            client_id: [&[1u8][..], &mac.0].concat(),
+            mac,
+            addr: Ipv4Addr::new(10, 0, 0, n),
+            expires,
+            state: LeaseState::Bound,
+            info,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let file = TempFile::new("round-trip.leases");
+        let lease_file = LeaseFile::new(file.path());
+        let expires = now() + 3600;
+        let full = lease(
+            1,
+            expires,
+            ClientInfo {
+                hostname: Some("Pixel-7".to_string()),
+                vendor_class: Some("android-dhcp-14".to_string()),
+                parameters: vec![1, 3, 6, 15],
+            },
+        );
+        let bare = lease(2, expires, ClientInfo::default());
+        lease_file.save([&full, &bare].into_iter()).unwrap();
+
+        let (mut leases, skipped) = lease_file.load().unwrap();
+        assert!(skipped.is_empty(), "{:?}", skipped);
+        leases.sort_by_key(|lease| lease.addr);
+        assert_eq!(leases.len(), 2);
+        for (loaded, saved) in leases.iter().zip([&full, &bare]) {
+            assert_eq!(loaded.client_id, saved.client_id);
+            assert_eq!(loaded.mac, saved.mac);
+            assert_eq!(loaded.addr, saved.addr);
+            assert_eq!(loaded.expires, saved.expires);
+            assert_eq!(loaded.state, LeaseState::Bound);
+            assert_eq!(loaded.info, saved.info);
+        }
+    }
+
+    #[test]
+    fn optional_fields_may_be_missing() {
+        let lease = LeaseFile::parse_line("1718031234 aa:bb:cc:dd:ee:ff 192.168.4.100 01aabbccddeeff").unwrap();
+        assert_eq!(lease.info, ClientInfo::default());
+
+        let lease = LeaseFile::parse_line("1718031234 aa:bb:cc:dd:ee:ff 192.168.4.100 01aabbccddeeff - - 0103").unwrap();
+        assert_eq!(lease.info.hostname, None);
+        assert_eq!(lease.info.vendor_class, None);
+        assert_eq!(lease.info.parameters, vec![1, 3]);
+    }
+
+    #[test]
+    fn corrupt_lines_are_skipped() {
+        let file = TempFile::new("corrupt.leases");
+        let expires = now() + 3600;
+        let lines = [
+            "# written by lilap".to_string(),
+            String::new(),
+            format!("{} aa:bb:cc:00:00:01 10.0.0.1 01aabbcc000001", expires),
+            format!("{} aa:bb:cc:00:00:02 10.0.0.2", expires),
+            "soon aa:bb:cc:00:00:03 10.0.0.3 01aabbcc000003".to_string(),
+            format!("{} aa:bb:cc:00:00 10.0.0.4 01aabbcc000004", expires),
+            format!("{} aa:bb:cc:00:00:05 10.0.0.300 01aabbcc000005", expires),
+            format!("{} aa:bb:cc:00:00:06 10.0.0.6 01aabbcc00000", expires),
+            format!("{} aa:bb:cc:00:00:07 10.0.0.7 01aabbcc000007 host zz -", expires),
+            format!("{} aa:bb:cc:00:00:08 10.0.0.8 01aabbcc000008 host - 0x", expires),
+        ];
+        fs::write(&file.0, lines.join("\n")).unwrap();
+
+        let (leases, skipped) = LeaseFile::new(file.path()).load().unwrap();
+        assert_eq!(leases.len(), 1);
+        assert_eq!(leases[0].addr, Ipv4Addr::new(10, 0, 0, 1));
+        let skipped_lines: Vec<&str> = skipped.iter().map(|problem| problem.split(':').next().unwrap()).collect();
+        assert_eq!(skipped_lines, ["line 4", "line 5", "line 6", "line 7", "line 8", "line 9", "line 10"]);
+    }
+
+    #[test]
+    fn expired_leases_are_dropped() {
+        let file = TempFile::new("expired.leases");
+        let lease_file = LeaseFile::new(file.path());
+        let running = lease(1, now() + 3600, ClientInfo::default());
+        let expired = lease(2, now() - 1, ClientInfo::default());
+        lease_file.save([&running, &expired].into_iter()).unwrap();
+
+        let (leases, skipped) = lease_file.load().unwrap();
+        assert!(skipped.is_empty(), "{:?}", skipped);
+        assert_eq!(leases.len(), 1);
+        assert_eq!(leases[0].addr, running.addr);
+    }
+
+    #[test]
+    fn missing_file_holds_no_leases() {
+        let file = TempFile::new("missing.leases");
+        let (leases, skipped) = LeaseFile::new(file.path()).load().unwrap();
+        assert!(leases.is_empty() && skipped.is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+use crate::server::iface::MacAddr;
+use crate::server::reload::WatchedFile;
+use std::str::FromStr;
+
+/// A full MAC address, or a prefix of one ending in `*` such as the OUI `aa:bb:cc:*`
+pub struct MacPattern {
+    prefix: Vec<u8>,
+}
+
+impl MacPattern {
+    pub fn matches(&self, mac: &MacAddr) -> bool {
+        mac.0.starts_with(&self.prefix)
+    }
+}
+
+impl FromStr for MacPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid MAC pattern: {}", s);
+        // Wildcards stand for whole octets only
+        let (octets, wildcard) = match s.strip_suffix('*') {
+            Some("") => ("", true),
+            Some(prefix) => (prefix.strip_suffix([':', '-']).ok_or_else(invalid)?, true),
+            None => (s, false),
+        };
+
+        let prefix = if octets.is_empty() {
+            Vec::new()
+        } else {
+            octets
+                .split([':', '-'])
+                .map(|octet| u8::from_str_radix(octet, 16).map_err(|_| invalid()))
+                .collect::<Result<Vec<u8>, String>>()?
+        };
+        if prefix.len() > 6 || (!wildcard && prefix.len() != 6) {
+            return Err(invalid());
+        }
+        Ok(MacPattern { prefix })
+    }
+}
+
+/// MAC patterns read from a file, one per line.
+///
+/// Empty lines and everything after a `#` are ignored. Once read, a list is
+/// kept until its file can be read again.
+pub struct MacList {
+    file: WatchedFile,
+    // None until the file was read once
+    patterns: Option<Vec<MacPattern>>,
+}
+
+impl MacList {
+    fn new(path: &str) -> Self {
+        MacList {
+            file: WatchedFile::new(path),
+            patterns: None,
+        }
+    }
+
+    /// Whether any entry matches, None if the list was never read
+    pub fn matches(&self, mac: &MacAddr) -> Option<bool> {
+        let patterns = self.patterns.as_ref()?;
+        Some(patterns.iter().any(|pattern| pattern.matches(mac)))
+    }
+
+    // Parses the file again if it changed, returning what happened for the log.
+    // `unread` tells what the filter does while the file was never read.
+    fn reload(&mut self, kind: &str, unread: &str) -> Vec<String> {
+        let contents = match self.file.poll() {
+            None => return Vec::new(),
+            Some(Ok(contents)) => contents,
+            Some(Err(e)) => {
+                return vec![match &self.patterns {
+                    Some(patterns) => format!("Could not read {} list {}, keeping {} entries: {}", kind, self.file.path(), patterns.len(), e),
+                    None => format!("Could not read {} list {}, {} until it can be read: {}", kind, self.file.path(), unread, e),
+                }];
+            }
+        };
+
+        let mut messages = Vec::new();
+        let mut patterns = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            match line.parse::<MacPattern>() {
+                Ok(pattern) => patterns.push(pattern),
+                Err(e) => messages.push(format!("Skipping line {} of {}: {}", i + 1, self.file.path(), e)),
+            }
+        }
+        messages.push(format!("Loaded {} entries from {} list {}", patterns.len(), kind, self.file.path()));
+        self.patterns = Some(patterns);
+        messages
+    }
+}
+
+/// Which clients get served at all.
+///
+/// A client is refused if the deny list matches it, or if there is an allow
+/// list and it does not. Without an allow list everyone not denied is served,
+/// but a configured allow list that was never read refuses everyone: a typo
+/// in its path must not open up the network.
+pub struct MacFilter {
+    allow: Option<MacList>,
+    deny: Option<MacList>,
+}
+
+impl MacFilter {
+    /// Creates the filter from the list files, an empty path meaning no such list
+    pub fn new(allow_path: &str, deny_path: &str) -> Self {
+        let list = |path: &str| Some(path).filter(|path| !path.is_empty()).map(MacList::new);
+        MacFilter {
+            allow: list(allow_path),
+            deny: list(deny_path),
+        }
+    }
+
+    /// Picks up changes to the list files, returning what happened for the log
+    pub fn reload(&mut self) -> Vec<String> {
+        let mut messages = Vec::new();
+        if let Some(allow) = &mut self.allow {
+            messages.extend(allow.reload("allow", "refusing every client"));
+        }
+        if let Some(deny) = &mut self.deny {
+            messages.extend(deny.reload("deny", "denying no one"));
+        }
+        messages
+    }
+
+    pub fn permits(&self, mac: &MacAddr) -> bool {
+        let denied = self.deny.as_ref().and_then(|deny| deny.matches(mac)).unwrap_or(false);
+        let allowed = self.allow.as_ref().is_none_or(|allow| allow.matches(mac).unwrap_or(false));
+        !denied && allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn mac(s: &str) -> MacAddr {
+        let mut mac = [0u8; 6];
+        for (octet, part) in mac.iter_mut().zip(s.split(':')) {
+            *octet = u8::from_str_radix(part, 16).unwrap();
+        }
+        MacAddr(mac)
+    }
+
+    // A list file path of its own for every test, removed on drop
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("lilap-{}-{}", std::process::id(), name));
+            let _ = fs::remove_file(&path);
+            TempFile(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn full_addresses() {
+        let pattern: MacPattern = "aa:bb:cc:00:00:01".parse().unwrap();
+        assert!(pattern.matches(&mac("aa:bb:cc:00:00:01")));
+        assert!(!pattern.matches(&mac("aa:bb:cc:00:00:02")));
+
+        let pattern: MacPattern = "AA-BB-CC-00-00-01".parse().unwrap();
+        assert!(pattern.matches(&mac("aa:bb:cc:00:00:01")));
+    }
+
+    #[test]
+    fn prefixes() {
+        let pattern: MacPattern = "aa:bb:cc:*".parse().unwrap();
+        assert!(pattern.matches(&mac("aa:bb:cc:12:34:56")));
+        assert!(!pattern.matches(&mac("aa:bb:cd:12:34:56")));
+
+        let pattern: MacPattern = "*".parse().unwrap();
+        assert!(pattern.matches(&mac("00:00:00:00:00:00")));
+
+        let pattern: MacPattern = "aa:bb:cc:00:00:01:*".parse().unwrap();
+        assert!(pattern.matches(&mac("aa:bb:cc:00:00:01")));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        for pattern in ["", "aa:bb:cc", "aa:bb:cc:00:00:01:02", "aa:bb:c*", "aa:bb:cc*", "aa:bb:zz:*", "aa::cc:*", "aa:bb:cc:00:00:100"] {
+            assert!(pattern.parse::<MacPattern>().is_err(), "{} parsed", pattern);
+        }
+    }
+
+    #[test]
+    fn missing_allow_list_refuses_everyone() {
+        let allow = TempFile::new("missing-allow");
+        let mut filter = MacFilter::new(allow.path(), "");
+        let messages = filter.reload();
+        assert!(messages[0].contains("refusing every client"), "{:?}", messages);
+        assert!(!filter.permits(&mac("aa:bb:cc:00:00:01")));
+        assert!(!filter.permits(&mac("aa:bb:cc:00:00:02")));
+
+        fs::write(&allow.0, "aa:bb:cc:00:00:02\n").unwrap();
+        filter.reload();
+        assert!(!filter.permits(&mac("aa:bb:cc:00:00:01")));
+        assert!(filter.permits(&mac("aa:bb:cc:00:00:02")));
+    }
+
+    #[test]
+    fn missing_deny_list_denies_no_one() {
+        let deny = TempFile::new("missing-deny");
+        let mut filter = MacFilter::new("", deny.path());
+        let messages = filter.reload();
+        assert!(messages[0].contains("denying no one"), "{:?}", messages);
+        assert!(filter.permits(&mac("aa:bb:cc:00:00:01")));
+    }
+
+    #[test]
+    fn read_lists_are_kept_when_the_file_goes() {
+        let allow = TempFile::new("kept-allow");
+        let deny = TempFile::new("kept-deny");
+        fs::write(&allow.0, "# staff\naa:bb:cc:* # laptops\n\nbogus\n").unwrap();
+        fs::write(&deny.0, "aa:bb:cc:00:00:66\n").unwrap();
+        let mut filter = MacFilter::new(allow.path(), deny.path());
+        let messages = filter.reload();
+        assert!(messages.iter().any(|message| message.starts_with("Skipping line 4")), "{:?}", messages);
+
+        fs::remove_file(&allow.0).unwrap();
+        fs::remove_file(&deny.0).unwrap();
+        let messages = filter.reload();
+        assert!(messages.iter().all(|message| message.contains("keeping 1 entries")), "{:?}", messages);
+
+        assert!(filter.permits(&mac("aa:bb:cc:00:00:01")));
+        assert!(!filter.permits(&mac("aa:bb:cc:00:00:66")));
+        assert!(!filter.permits(&mac("02:00:00:00:00:01")));
+    }
+}
@@ -1,61 +1,193 @@
+pub mod hook;
+pub mod lease;
+pub mod lease_file;
+pub mod mac_filter;
+pub mod options;
+pub mod packet;
+pub mod pool;
+pub mod probe;
+pub mod raw;
+pub mod scope;
+
+use crate::server::events::{self, LeaseEvent};
+use crate::server::registry::{self, Client};
 use crate::{lock, receiver, server::*, server_state};
 use confee::conf::*;
-use std::net::{IpAddr, UdpSocket, SocketAddr};
-use std::io::{self, Cursor, Write, Read};
-use byteorder::{BigEndian, ReadBytesExt};
-use std::time::Duration;
+use hook::Hook;
+use crate::server::iface::{self, MacAddr};
+use crate::server::portal;
+use lease::{Lease, LeaseState, Reservation};
+use lease_file::LeaseFile;
+use mac_filter::MacFilter;
+use options::*;
+use packet::{DhcpPacket, BOOTREQUEST};
+use pool::Pool;
+use probe::ArpProber;
+use raw::PacketSocket;
+use scope::{Scope, ScopeLink};
+use std::net::{Ipv4Addr, UdpSocket, SocketAddr, SocketAddrV4};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use std::sync::mpsc;
 
+// DHCP message types (option 53)
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPDECLINE: u8 = 4;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+const DHCPRELEASE: u8 = 7;
+const DHCPINFORM: u8 = 8;
+
+// Addresses tried for a single DISCOVER before giving up on probing
+const PROBE_ATTEMPTS: usize = 4;
+// How often expired leases and changed MAC lists are looked for
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct Dhcp {
-    addr: IpAddr,
     port: u16,
     dst_port: u16,
+    probe_timeout: Duration,
+    // The scope of link_iface first, then the ones from dhcp_scopes
+    scopes: Vec<Scope>,
+    // Written by both the receive loop and the reaper
+    lease_file: Option<Mutex<LeaseFile>>,
+    hook: Option<Hook>,
+    mac_filter: Mutex<MacFilter>,
     pub state: ServerState,
 }
 
+/// An offer held back until the ARP probe for its address goes unanswered
+struct PendingOffer<'a> {
+    request: DhcpPacket,
+    scope: &'a Scope,
+    pool: &'a Pool,
+    link: &'a ScopeLink,
+    addr: Ipv4Addr,
+    deadline: Instant,
+    // Addresses probed for this DISCOVER so far, this one included
+    attempts: usize,
+}
+
 impl Server for Dhcp {
     fn create(conf: &Conf) -> Self {
+        let lease_file_path: String = conf.get("dhcp_lease_file").unwrap();
+        let lease_file = if lease_file_path.is_empty() {
+            None
+        } else {
+            Some(Mutex::new(LeaseFile::new(&lease_file_path)))
+        };
+        let hook_path: String = conf.get("dhcp_script").unwrap();
+        let hook = if hook_path.is_empty() {
+            None
+        } else {
+            Some(Hook::new(&hook_path, Duration::from_secs(conf.get("dhcp_script_timeout").unwrap())))
+        };
+
         let mut dhcp = Dhcp {
-            addr: conf.get("link_addr").unwrap(),
             port: conf.get("dhcp_src_port").unwrap(),
             dst_port: conf.get("dhcp_dst_port").unwrap(),
+            probe_timeout: Duration::from_millis(conf.get("dhcp_probe_timeout").unwrap()),
+            scopes: Vec::new(),
+            lease_file,
+            hook,
+            mac_filter: Mutex::new(MacFilter::new(&conf["dhcp_allow_file"], &conf["dhcp_deny_file"])),
             state: server_state!(),
         };
         dhcp.state.prefix = String::from("dhcp");
+
+        // DNS and the portal only listen on link_addr, every scope points its clients there
+        let link_addr: Ipv4Addr = conf.get("link_addr").unwrap();
+        let scope = dhcp.create_scope("default", conf, link_addr).unwrap_or_else(|e| panic!("{}: {}", dhcp.state.prefix, e));
+        dhcp.scopes.push(scope);
+        for path in conf["dhcp_scopes"].split(',').map(str::trim).filter(|path| !path.is_empty()) {
+            let name = Path::new(path).file_stem().and_then(|stem| stem.to_str()).unwrap_or(path);
+            match Self::scope_conf(conf, path).and_then(|scope_conf| dhcp.create_scope(name, &scope_conf, link_addr)) {
+                Ok(scope) => dhcp.scopes.push(scope),
+                Err(e) => dhcp.log(&format!("Ignoring scope {}: {}", name, e)),
+            }
+        }
+        dhcp.load_leases();
+        dhcp.reload_mac_filter();
         dhcp
     }
 
     fn mainloop(&self) {
-        let socket_addr = SocketAddr::new(self.addr, self.port);
-        let socket = UdpSocket::bind(socket_addr).expect(&format!("{}: Could not bind to address", self.state.prefix));
-        socket.set_nonblocking(true).expect(&format!("{}: Failed to set non-blocking", self.state.prefix));
+        // Clients without an address broadcast, so listen on every address and tell scopes apart by the receiving interface
+        let socket_addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, self.port));
+        let socket = UdpSocket::bind(socket_addr).unwrap_or_else(|_| panic!("{}: Could not bind to address", self.state.prefix));
+        raw::enable_pktinfo(&socket).unwrap_or_else(|e| panic!("{}: Failed to enable IP_PKTINFO: {}", self.state.prefix, e));
+        socket.set_broadcast(true).unwrap_or_else(|_| panic!("{}: Failed to enable broadcast", self.state.prefix));
+        socket.set_nonblocking(true).unwrap_or_else(|_| panic!("{}: Failed to set non-blocking", self.state.prefix));
 
+        let links: Vec<Option<ScopeLink>> = self.scopes.iter().map(|scope| self.open_link(scope)).collect();
+        for scope in &self.scopes {
+            for pool in scope.pools() {
+                lock!(pool.leases, leases => {
+                    self.log(&format!(
+                        "Serving {} addresses on {} in scope {} ({}), {}s leases",
+                        leases.pool_size(),
+                        pool.network(),
+                        scope.name,
+                        scope.iface,
+                        leases.lease_time()
+                    ));
+                });
+            }
+        }
+
+        let running = AtomicBool::new(true);
+        thread::scope(|s| {
+            s.spawn(|| self.maintain(&running));
+            if let Some(hook) = &self.hook {
+                // Subscribe right away so no event is missed while the thread starts
+                let lease_events = events::subscribe();
+                s.spawn(|| self.run_hook(hook, lease_events, &running));
+            }
+            self.serve(&socket, &links);
+            running.store(false, Ordering::Relaxed);
+        });
+
+        self.log("Stopped");
+    }
+}
+
+impl Dhcp {
+    fn serve(&self, socket: &UdpSocket, links: &[Option<ScopeLink>]) {
+        let mut pending = Vec::new();
         loop {
             lock!(receiver!(self), rx => {
-                if let Ok(_) = rx.try_recv() {
+                if rx.try_recv().is_ok() {
                     self.log("Stop signal received. Shutting down.");
                     break;
                 }
             });
 
-            let mut buffer = [0; 512];
-            match socket.recv_from(&mut buffer) {
-                Ok((ref mut n, addr)) => {
+            let mut buffer = [0; 1500];
+            match raw::recv_with_iface(socket, &mut buffer) {
+                Ok((ref mut n, addr, ifindex)) => {
                     self.log(&format!("New request from {}", addr));
                     self.log(&format!("Received {} bytes of data:\n{}", n, self.format_bytes_as_hex(&buffer, *n)));
-                    if let Some((xid, client_mac)) = self.parse_dhcp_request(&buffer[..*n]) {
-                        let response = self.create_dhcp_offer(xid, &client_mac);
-                        let response_addr = (addr.ip(), self.dst_port);
-                        match socket.send_to(&response[..], response_addr) {
-                            Ok(sent_bytes) => {
-                                self.log(&format!("Sent {} bytes to {}:\n{}", sent_bytes, addr, self.format_bytes_as_hex(&response[..], sent_bytes)));
+                    match DhcpPacket::parse(&buffer[..*n]) {
+                        Ok(request) if request.op == BOOTREQUEST => match self.scope_for(&request, ifindex, links) {
+                            Ok((scope, pool, link)) => {
+                                if let Some(reply) = self.handle_request(&request, scope, pool, link, &mut pending) {
+                                    self.send_reply(socket, scope, link, &request, &reply);
+                                }
                             }
                             Err(e) => {
-                                self.log(&format!("Failed to send response to {}: {}", addr, e));
+                                self.log(&format!("{}, ignoring request from {}", e, request.mac()));
                             }
+                        },
+                        Ok(request) => {
+                            self.log(&format!("Ignoring BOOTP op {} from {}", request.op, addr));
+                        }
+                        Err(e) => {
+                            self.log(&format!("Dropping request from {}: {}", addr, e));
                         }
-                    } else {
-                        self.log("Failed to parse DHCP request.");
                     }
                     self.log("Waiting for requests...");
                 }
@@ -66,20 +198,51 @@ impl Server for Dhcp {
                     self.log(&format!("Error receiving request: {}", e));
                 }
             }
+            self.check_probes(socket, links, &mut pending);
+
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // Runs the hook for one event after the other, away from the receive loop
+    fn run_hook(&self, hook: &Hook, lease_events: mpsc::Receiver<LeaseEvent>, running: &AtomicBool) {
+        while running.load(Ordering::Relaxed) {
+            for event in lease_events.try_iter() {
+                if let Err(e) = hook.run(&event) {
+                    self.log(&format!("Hook {} failed for {}: {}", hook.path(), event.client().addr, e));
+                }
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
 
+    // Frees leases that ran out and picks up edited MAC lists, even when no request comes in
+    fn maintain(&self, running: &AtomicBool) {
+        let mut last_run = Instant::now();
+        while running.load(Ordering::Relaxed) {
+            if last_run.elapsed() >= MAINTENANCE_INTERVAL {
+                for pool in self.pools() {
+                    self.expire_leases(pool);
+                }
+                self.reload_mac_filter();
+                last_run = Instant::now();
+            }
             thread::sleep(Duration::from_millis(10));
         }
+    }
 
-        self.log("Stopped");
+    fn reload_mac_filter(&self) {
+        let messages = lock!(self.mac_filter, mac_filter => mac_filter.reload());
+        for message in messages {
+            self.log(&message);
+        }
     }
-}
 
-impl Dhcp {
     fn format_bytes_as_hex(&self, buffer: &[u8], n: usize) -> String {
         let bytes_to_read = n.min(buffer.len());
         let hex_bytes: Vec<String> = buffer[..bytes_to_read]
             .iter()
-            .map(|byte| format!("{:02x}", byte)) 
+            .map(|byte| format!("{:02x}", byte))
             .collect();
         let mut hex_output = String::new();
         for chunk in hex_bytes.chunks(32) {
@@ -89,36 +252,586 @@ impl Dhcp {
         hex_output
     }
 
-    fn parse_dhcp_request(&self, buffer: &[u8]) -> Option<(u32, Vec<u8>)> {
-        // Transaction ID is bytes 4 to 8
-        let xid = (&buffer[4..8]).read_u32::<BigEndian>().ok()?;
-    
-        // MAC address is bytes 28 to 34 (assuming Ethernet hardware)
-        let client_mac = buffer[28..34].to_vec();
-    
-        Some((xid, client_mac))
-    }
-
-    fn create_dhcp_offer(&self, transaction_id: u32, client_mac: &[u8]) -> Vec<u8> {
-        let mut response = vec![0u8; 240]; // Basic DHCP response structure size
-    
-        // Fill in some of the basic fields (op, htype, xid, etc.)
-        response[0] = 2; // op: 2 = BOOTREPLY
-        response[1] = 1; // htype: Ethernet
-        response[2] = 6; // hlen: MAC address length
-        response[4..8].copy_from_slice(&transaction_id.to_be_bytes());
-    
-        // Copy the client MAC address into the response
-        response[28..34].copy_from_slice(&client_mac);
-    
-        // Fill in DHCP options, including DHCP message type (DHCPOFFER)
-        response.extend(&[53, 1, 2]); // Option 53 (Message Type) -> DHCPOFFER
-    
-        // Optionally, include more options like the offered IP address, lease time, etc.
-    
-        response.push(255); // End option
-    
-        response
-    }
-    
-}
\ No newline at end of file
+    // Comma separated addresses, None when empty so the caller can fall back to link_addr
+    fn parse_addr_list(value: &str) -> Option<Vec<Ipv4Addr>> {
+        let addrs: Vec<Ipv4Addr> = value
+            .split(',')
+            .filter_map(|addr| addr.trim().parse().ok())
+            .collect();
+        if addrs.is_empty() {
+            None
+        } else {
+            Some(addrs)
+        }
+    }
+
+    // A scope file holds the same link_* and dhcp_* keys as the main configuration.
+    // Settings that only make sense for one subnet must be given, the rest is inherited.
+    fn scope_conf(conf: &Conf, path: &str) -> Result<Conf, String> {
+        let inherited = |key: &str| (key.to_string(), conf[key].clone());
+        let unset = |key: &str| (key.to_string(), String::new());
+        let mut scope_conf = Conf::from([
+            unset("link_iface"),
+            unset("link_addr"),
+            unset("dhcp_pool_start"),
+            unset("dhcp_pool_end"),
+            inherited("dhcp_subnet_mask"),
+            inherited("dhcp_lease_time"),
+            unset("dhcp_server_id"),
+            unset("dhcp_router"),
+            inherited("dhcp_dns_server"),
+            inherited("dhcp_domain"),
+            inherited("dhcp_renewal_time"),
+            inherited("dhcp_rebinding_time"),
+            inherited("dhcp_captive_portal_uri"),
+            inherited("web_port"),
+            unset("dhcp_reservations"),
+            unset("dhcp_relay_pools"),
+        ]);
+        scope_conf.with_file(path).update()?;
+        Ok(scope_conf)
+    }
+
+    fn create_scope(&self, name: &str, conf: &Conf, link_addr: Ipv4Addr) -> Result<Scope, String> {
+        let iface: String = conf["link_iface"].clone();
+        if iface.is_empty() {
+            return Err("link_iface is not set".to_string());
+        }
+        if self.scopes.iter().any(|scope| scope.iface == iface) {
+            return Err(format!("{} is already served", iface));
+        }
+        let invalid = |key: &str| format!("{} is missing or invalid", key);
+        let addr: Ipv4Addr = conf.get("link_addr").ok_or_else(|| invalid("link_addr"))?;
+        let lease_time: u32 = conf.get("dhcp_lease_time").ok_or_else(|| invalid("dhcp_lease_time"))?;
+        let start: Ipv4Addr = conf.get("dhcp_pool_start").ok_or_else(|| invalid("dhcp_pool_start"))?;
+        let end: Ipv4Addr = conf.get("dhcp_pool_end").ok_or_else(|| invalid("dhcp_pool_end"))?;
+        let mask: Ipv4Addr = conf.get("dhcp_subnet_mask").ok_or_else(|| invalid("dhcp_subnet_mask"))?;
+        if start > end {
+            return Err(format!("dhcp_pool_start {} is past dhcp_pool_end {}", start, end));
+        }
+        let network = |addr: Ipv4Addr| u32::from(addr) & u32::from(mask);
+        if network(start) != network(addr) || network(end) != network(addr) {
+            return Err(format!(
+                "Pool {}-{} is not inside {}/{}, the subnet of link_addr {}",
+                start,
+                end,
+                Ipv4Addr::from(network(addr)),
+                mask,
+                addr
+            ));
+        }
+        let pool = Pool::new(
+            start,
+            end,
+            mask,
+            Self::parse_addr_list(&conf["dhcp_router"]).unwrap_or_else(|| vec![addr]),
+            // Never hand out our own address
+            vec![addr],
+            lease_time,
+        );
+        if self.overlaps(&pool, std::iter::empty()) {
+            return Err(format!("{} overlaps another scope", pool.network()));
+        }
+
+        let mut scope = Scope {
+            name: name.to_string(),
+            iface,
+            addr,
+            server_id: conf.get("dhcp_server_id").unwrap_or(addr),
+            dns_servers: Self::parse_addr_list(&conf["dhcp_dns_server"]).unwrap_or_else(|| vec![link_addr]),
+            domain: conf["dhcp_domain"].clone(),
+            renewal_time: conf.get("dhcp_renewal_time"),
+            rebinding_time: conf.get("dhcp_rebinding_time"),
+            captive_portal_uri: portal::captive_portal_uri(conf, link_addr),
+            pool,
+            relay_pools: Vec::new(),
+        };
+        self.load_relay_pools(&mut scope, &conf["dhcp_relay_pools"], lease_time);
+        self.load_reservations(&scope, &conf["dhcp_reservations"]);
+        Ok(scope)
+    }
+
+    // Whether the pool shares addresses with any pool served so far
+    fn overlaps<'a>(&'a self, pool: &Pool, others: impl Iterator<Item = &'a Pool>) -> bool {
+        self.pools()
+            .chain(others)
+            .any(|existing| existing.contains(pool.network()) || pool.contains(existing.network()))
+    }
+
+    // Comma separated list of `<start>-<end>/<mask> [router]` entries
+    fn load_relay_pools(&self, scope: &mut Scope, value: &str, lease_time: u32) {
+        for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            match Pool::parse_relayed(entry, lease_time) {
+                Ok(pool) if self.overlaps(&pool, scope.pools()) => {
+                    self.log(&format!("Ignoring relay pool '{}': overlaps another pool", entry));
+                }
+                Ok(pool) => scope.relay_pools.push(pool),
+                Err(e) => self.log(&format!("Ignoring relay pool '{}': {}", entry, e)),
+            }
+        }
+    }
+
+    fn pools(&self) -> impl Iterator<Item = &Pool> {
+        self.scopes.iter().flat_map(|scope| scope.pools())
+    }
+
+    // Pool of the subnet an address belongs to, the default scope's for addresses we do not know
+    fn pool_of(&self, addr: Ipv4Addr) -> &Pool {
+        self.pools().find(|pool| pool.contains(addr)).unwrap_or(&self.scopes[0].pool)
+    }
+
+    // Relayed requests are served from the pool of the relay's subnet, as long as they came in on
+    // the interface of the scope it is relayed to: the socket listens on every interface, and the
+    // giaddr is whatever the sender claims. The others only from the scope of the interface they
+    // were received on. Says why when there is no pool for the request.
+    fn scope_for<'a>(&'a self, request: &DhcpPacket, ifindex: i32, links: &'a [Option<ScopeLink>]) -> Result<(&'a Scope, &'a Pool, Option<&'a ScopeLink>), String> {
+        if !request.giaddr.is_unspecified() {
+            let (scope, link, pool) = self
+                .scopes
+                .iter()
+                .zip(links)
+                .find_map(|(scope, link)| scope.pools().find(|pool| pool.contains(request.giaddr)).map(|pool| (scope, link, pool)))
+                .ok_or_else(|| format!("No pool for relay agent {}", request.giaddr))?;
+            if link.as_ref().is_none_or(|link| link.ifindex != ifindex) {
+                return Err(format!("Relay agent {} belongs to scope {} on {}, not interface {}", request.giaddr, scope.name, scope.iface, ifindex));
+            }
+            return Ok((scope, pool, None));
+        }
+        self.scopes
+            .iter()
+            .zip(links)
+            .find_map(|(scope, link)| link.as_ref().filter(|link| link.ifindex == ifindex).map(|link| (scope, &scope.pool, Some(link))))
+            .ok_or_else(|| format!("No scope on interface {}", ifindex))
+    }
+
+    // Comma separated list of `<mac> <addr> [hostname]` entries
+    fn load_reservations(&self, scope: &Scope, value: &str) {
+        for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let result = entry.parse::<Reservation>().and_then(|reservation| {
+                if let Some((_, existing)) = scope.reservation(&reservation.mac) {
+                    return Err(format!("{} already has {} reserved", reservation.mac, existing.addr));
+                }
+                let pool = scope
+                    .pools()
+                    .find(|pool| pool.contains(reservation.addr))
+                    .ok_or_else(|| format!("{} is outside every subnet of scope {}", reservation.addr, scope.name))?;
+                lock!(pool.leases, leases => leases.add_reservation(reservation))
+            });
+            match result {
+                Ok(_) => self.log(&format!("Reserved {}", entry)),
+                Err(e) => self.log(&format!("Ignoring reservation '{}': {}", entry, e)),
+            }
+        }
+    }
+
+    fn load_leases(&self) {
+        let Some(lease_file) = &self.lease_file else {
+            return;
+        };
+
+        let lease_file = lease_file.lock().unwrap();
+        match lease_file.load() {
+            Ok((loaded, skipped)) => {
+                for problem in skipped {
+                    self.log(&format!("Skipping corrupted entry in {}, {}", lease_file.path(), problem));
+                }
+                let mut restored = 0;
+                for lease in loaded {
+                    let client = Client::from(&lease);
+                    match lock!(self.pool_of(lease.addr).leases, leases => leases.restore(lease)) {
+                        Ok(_) => {
+                            // The servers created before DHCP may have looked at the registry already, so announce it like a new lease
+                            registry::register(client.clone());
+                            events::publish(&LeaseEvent::Granted(client));
+                            restored += 1;
+                        }
+                        Err(e) => self.log(&format!("Dropping stored lease of {} for {}: {}", client.addr, client, e)),
+                    }
+                }
+                self.log(&format!("Restored {} leases from {}", restored, lease_file.path()));
+            }
+            Err(e) => {
+                self.log(&format!("Could not read lease file {}: {}", lease_file.path(), e));
+            }
+        }
+    }
+
+    // Writes the bound leases of every pool, so no pool lock may be held when calling it
+    fn save_leases(&self) {
+        let Some(lease_file) = &self.lease_file else {
+            return;
+        };
+
+        lock!(lease_file, lease_file => {
+            let mut bound = Vec::new();
+            for pool in self.pools() {
+                lock!(pool.leases, leases => bound.extend(leases.bound().cloned()));
+            }
+            if let Err(e) = lease_file.save(bound.iter()) {
+                self.log(&format!("Could not write lease file {}: {}", lease_file.path(), e));
+            }
+        });
+    }
+
+    fn expire_leases(&self, pool: &Pool) {
+        let expired = lock!(pool.leases, leases => leases.expire());
+        for lease in &expired {
+            self.notify(LeaseEvent::Expired(Client::from(lease)));
+        }
+        if !expired.is_empty() {
+            self.save_leases();
+        }
+    }
+
+    // Tells the registry and every subscriber about a lease change
+    fn notify(&self, event: LeaseEvent) {
+        self.log(&event.to_string());
+        let client = event.client();
+        if event.is_end() {
+            registry::unregister(&client.mac, client.addr);
+        } else {
+            registry::register(client.clone());
+        }
+        events::publish(&event);
+    }
+
+    // A lease given back by the client, if the client actually held it
+    fn end_lease(&self, lease: Option<Lease>) {
+        if let Some(lease) = lease.filter(|lease| lease.state == LeaseState::Bound) {
+            self.notify(LeaseEvent::Released(Client::from(&lease)));
+            self.save_leases();
+        }
+    }
+
+    fn open_link(&self, scope: &Scope) -> Option<ScopeLink> {
+        let ifindex = match iface::interface_index(&scope.iface) {
+            Ok(ifindex) => ifindex,
+            Err(e) => {
+                self.log(&format!("Not serving scope {} on {}: {}", scope.name, scope.iface, e));
+                return None;
+            }
+        };
+
+        let packet_socket = match PacketSocket::open(ifindex) {
+            Ok(packet_socket) => Some(packet_socket),
+            Err(e) => {
+                self.log(&format!("Cannot unicast to clients without an address on {}, broadcasting instead: {}", scope.iface, e));
+                None
+            }
+        };
+        let prober = if self.probe_timeout.is_zero() {
+            None
+        } else {
+            match iface::interface_mac(&scope.iface).and_then(|mac| ArpProber::open(ifindex, mac, scope.addr)) {
+                Ok(prober) => Some(prober),
+                Err(e) => {
+                    self.log(&format!("Cannot probe for address conflicts on {}: {}", scope.iface, e));
+                    None
+                }
+            }
+        };
+        Some(ScopeLink { ifindex, packet_socket, prober })
+    }
+
+    // Relayed requests come without a link, their clients are out of our reach
+    fn handle_request<'a>(
+        &self,
+        request: &DhcpPacket,
+        scope: &'a Scope,
+        pool: &'a Pool,
+        link: Option<&'a ScopeLink>,
+        pending: &mut Vec<PendingOffer<'a>>,
+    ) -> Option<DhcpPacket> {
+        let mac = request.mac();
+        let client_id = request.client_id();
+        if !lock!(self.mac_filter, mac_filter => mac_filter.permits(&mac)) {
+            self.log(&format!("Ignoring {}, refused by the MAC lists", request.client_info().label(&mac)));
+            return None;
+        }
+        // A client with a reservation is only served on the subnet of its reserved address
+        if let Some((reserved_pool, reservation)) = scope.reservation(&mac) {
+            if !std::ptr::eq(reserved_pool, pool) {
+                let label = request.client_info().label(&mac);
+                self.log(&format!("Ignoring {}, {} is reserved for it on {}", label, reservation.addr, reserved_pool.network()));
+                return None;
+            }
+        }
+
+        match request.message_type() {
+            Some(DHCPDISCOVER) => {
+                let label = request.client_info().label(&mac);
+                self.log(&format!("DHCPDISCOVER from {}", label));
+                if pending.iter().any(|offer| offer.request.client_id() == client_id) {
+                    self.log(&format!("Still probing for an address for {}", label));
+                    return None;
+                }
+                self.offer_address(request, scope, pool, link, 0, pending)
+            }
+            Some(DHCPREQUEST) => self.handle_dhcp_request(request, scope, pool),
+            Some(DHCPDECLINE) => {
+                if let Some(addr) = request.requested_addr() {
+                    self.log(&format!("DHCPDECLINE {} from {}", addr, mac));
+                    let lease = lock!(pool.leases, leases => leases.decline(&client_id, addr));
+                    self.end_lease(lease);
+                }
+                None
+            }
+            Some(DHCPRELEASE) => {
+                self.log(&format!("DHCPRELEASE {} from {}", request.ciaddr, mac));
+                let lease = lock!(pool.leases, leases => leases.release(&client_id, request.ciaddr));
+                self.end_lease(lease);
+                None
+            }
+            Some(DHCPINFORM) => {
+                self.log(&format!("DHCPINFORM from {} ({})", request.ciaddr, mac));
+                Some(self.create_dhcp_reply(request, scope, pool, DHCPACK, Ipv4Addr::UNSPECIFIED))
+            }
+            Some(other) => {
+                self.log(&format!("Ignoring DHCP message type {}", other));
+                None
+            }
+            None => {
+                // Plain BOOTP, we only speak DHCP
+                self.log(&format!("Ignoring BOOTP request from {}", mac));
+                None
+            }
+        }
+    }
+
+    // Offers the client an address. An address new to the client is probed for
+    // first, and the offer waits in `pending` until the probe goes unanswered.
+    fn offer_address<'a>(
+        &self,
+        request: &DhcpPacket,
+        scope: &'a Scope,
+        pool: &'a Pool,
+        link: Option<&'a ScopeLink>,
+        attempts: usize,
+        pending: &mut Vec<PendingOffer<'a>>,
+    ) -> Option<DhcpPacket> {
+        let mac = request.mac();
+        let client_id = request.client_id();
+        let info = request.client_info();
+
+        self.expire_leases(pool);
+        let offered = lock!(pool.leases, leases => {
+            let previous = leases.address_of(&client_id);
+            let addr = leases.offer(&client_id, mac, request.requested_addr(), &info);
+            addr.map(|addr| (addr, previous == Some(addr) || leases.reservation(&mac).is_some()))
+        });
+        let Some((addr, known)) = offered else {
+            self.log("No free address left to offer.");
+            return None;
+        };
+
+        let probing = link.filter(|_| !known).and_then(|link| link.prober.as_ref().map(|prober| (link, prober)));
+        if let Some((link, prober)) = probing {
+            match prober.send_probe(addr) {
+                Ok(()) => {
+                    pending.push(PendingOffer {
+                        request: request.clone(),
+                        scope,
+                        pool,
+                        link,
+                        addr,
+                        deadline: Instant::now() + self.probe_timeout,
+                        attempts: attempts + 1,
+                    });
+                    return None;
+                }
+                Err(e) => self.log(&format!("Failed to probe {}: {}", addr, e)),
+            }
+        }
+        self.log(&format!("DHCPOFFER {} to {}", addr, info.label(&mac)));
+        Some(self.create_dhcp_reply(request, scope, pool, DHCPOFFER, addr))
+    }
+
+    // Sends the held back offers whose probe went unanswered, and moves the
+    // ones whose address turned out to be in use on to another address
+    fn check_probes<'a>(&self, socket: &UdpSocket, links: &'a [Option<ScopeLink>], pending: &mut Vec<PendingOffer<'a>>) {
+        // Read even with nothing pending, so no stale claim is left for the next probe
+        let mut claims = Vec::new();
+        for link in links.iter().flatten() {
+            let Some(prober) = &link.prober else {
+                continue;
+            };
+            match prober.claims() {
+                Ok(found) => claims.extend(found.into_iter().map(|(addr, owner)| (link.ifindex, addr, owner))),
+                Err(e) => self.log(&format!("Failed to read ARP packets: {}", e)),
+            }
+        }
+
+        let now = Instant::now();
+        for offer in std::mem::take(pending) {
+            let mac = offer.request.mac();
+            let owner = claims
+                .iter()
+                .find(|(ifindex, addr, owner)| *ifindex == offer.link.ifindex && *addr == offer.addr && *owner != mac)
+                .map(|(_, _, owner)| owner);
+            let reply = if let Some(owner) = owner {
+                self.log(&format!("{} is already used by {}, not offering it", offer.addr, owner));
+                lock!(offer.pool.leases, leases => leases.mark_in_use(offer.addr));
+                if offer.attempts >= PROBE_ATTEMPTS {
+                    self.log("No free address left to offer.");
+                    continue;
+                }
+                self.offer_address(&offer.request, offer.scope, offer.pool, Some(offer.link), offer.attempts, pending)
+            } else if offer.deadline <= now {
+                self.log(&format!("DHCPOFFER {} to {}", offer.addr, offer.request.client_info().label(&mac)));
+                Some(self.create_dhcp_reply(&offer.request, offer.scope, offer.pool, DHCPOFFER, offer.addr))
+            } else {
+                pending.push(offer);
+                continue;
+            };
+            if let Some(reply) = reply {
+                self.send_reply(socket, offer.scope, Some(offer.link), &offer.request, &reply);
+            }
+        }
+    }
+
+    fn handle_dhcp_request(&self, request: &DhcpPacket, scope: &Scope, pool: &Pool) -> Option<DhcpPacket> {
+        let mac = request.mac();
+        let client_id = request.client_id();
+        let info = request.client_info();
+        let label = info.label(&mac);
+
+        // SELECTING: the client names the server whose offer it took
+        if let Some(server_id) = request.server_id() {
+            if server_id != scope.server_id {
+                self.log(&format!("{} chose server {}, dropping our offer", mac, server_id));
+                lock!(pool.leases, leases => leases.forget_offer(&client_id));
+                return None;
+            }
+        }
+
+        // INIT-REBOOT carries the address in option 50, RENEWING/REBINDING in ciaddr
+        let requested = match request.requested_addr() {
+            Some(addr) => addr,
+            None if !request.ciaddr.is_unspecified() => request.ciaddr,
+            None => {
+                self.log(&format!("DHCPREQUEST from {} without an address", mac));
+                return Some(self.create_dhcp_reply(request, scope, pool, DHCPNAK, Ipv4Addr::UNSPECIFIED));
+            }
+        };
+        self.log(&format!("DHCPREQUEST {} from {}", requested, label));
+
+        self.expire_leases(pool);
+        let bound = lock!(pool.leases, leases => {
+            let renewed = leases.is_bound(&client_id, requested);
+            leases.bind(&client_id, mac, requested, info).map(|lease| (lease, renewed))
+        });
+        if let Some((lease, renewed)) = bound {
+            let client = Client::from(&lease);
+            self.notify(if renewed { LeaseEvent::Renewed(client) } else { LeaseEvent::Granted(client) });
+            self.save_leases();
+            self.log(&format!("DHCPACK {} to {}", requested, label));
+            Some(self.create_dhcp_reply(request, scope, pool, DHCPACK, requested))
+        } else {
+            self.log(&format!("DHCPNAK {} to {}", requested, label));
+            Some(self.create_dhcp_reply(request, scope, pool, DHCPNAK, Ipv4Addr::UNSPECIFIED))
+        }
+    }
+
+    // RFC 2131 section 4.1: relayed replies go back to the relay agent, NAKs
+    // are broadcast, clients with an address get plain unicast, and clients
+    // without one get either a broadcast or, if they did not ask for it, a
+    // unicast to their hardware address. Everything but relayed replies
+    // leaves through the interface of the scope.
+    fn send_reply(&self, socket: &UdpSocket, scope: &Scope, link: Option<&ScopeLink>, request: &DhcpPacket, reply: &DhcpPacket) {
+        let (response, dropped) = reply.serialize(request.max_message_size());
+        if !dropped.is_empty() {
+            self.log(&format!("Options {:?} did not fit in the reply", dropped));
+        }
+        let response = response.as_slice();
+        let ifindex = link.map(|link| link.ifindex).unwrap_or(0);
+        let send = |dst: SocketAddrV4| (raw::send_with_iface(socket, response, dst, ifindex, scope.addr), dst);
+        let broadcast = SocketAddrV4::new(Ipv4Addr::BROADCAST, self.dst_port);
+        let (result, dst) = if !request.giaddr.is_unspecified() {
+            send(SocketAddrV4::new(request.giaddr, self.port))
+        } else if reply.message_type() == Some(DHCPNAK) {
+            send(broadcast)
+        } else if !request.ciaddr.is_unspecified() {
+            send(SocketAddrV4::new(request.ciaddr, self.dst_port))
+        } else if request.is_broadcast() || reply.yiaddr.is_unspecified() {
+            send(broadcast)
+        } else {
+            let src = SocketAddrV4::new(scope.addr, self.port);
+            let dst = SocketAddrV4::new(reply.yiaddr, self.dst_port);
+            let packet_socket = link.and_then(|link| link.packet_socket.as_ref());
+            match packet_socket.map(|packet_socket| packet_socket.send_udp(src, dst, &request.mac(), response)) {
+                Some(Ok(sent_bytes)) => (Ok(sent_bytes), dst),
+                failed => {
+                    if let Some(Err(e)) = failed {
+                        self.log(&format!("Failed to unicast to {} at {}, broadcasting instead: {}", dst, request.mac(), e));
+                    }
+                    send(broadcast)
+                }
+            }
+        };
+
+        match result {
+            Ok(sent_bytes) => {
+                self.log(&format!("Sent {} bytes to {}:\n{}", sent_bytes, dst, self.format_bytes_as_hex(response, sent_bytes)));
+            }
+            Err(e) => {
+                self.log(&format!("Failed to send response to {}: {}", dst, e));
+            }
+        }
+    }
+
+    fn create_dhcp_reply(&self, request: &DhcpPacket, scope: &Scope, pool: &Pool, msg_type: u8, yiaddr: Ipv4Addr) -> DhcpPacket {
+        let mut reply = request.reply();
+        if msg_type != DHCPNAK {
+            reply.ciaddr = request.ciaddr;
+        } else if !request.giaddr.is_unspecified() {
+            // The relay agent has to broadcast NAKs to the client
+            reply.set_broadcast();
+        }
+        reply.yiaddr = yiaddr;
+        reply.options = self.create_dhcp_options(scope, pool, msg_type, yiaddr, &request.mac(), request.giaddr);
+
+        // RFC 3046: relay agent information goes back unchanged, as the last option
+        if let Some(relay_info) = request.options.get(OPT_RELAY_AGENT_INFO) {
+            reply.options.push(OPT_RELAY_AGENT_INFO, relay_info);
+        }
+        reply
+    }
+
+    fn create_dhcp_options(&self, scope: &Scope, pool: &Pool, msg_type: u8, yiaddr: Ipv4Addr, mac: &MacAddr, giaddr: Ipv4Addr) -> DhcpOptions {
+        let mut options = DhcpOptions::new();
+        options.push(OPT_MESSAGE_TYPE, &[msg_type]);
+        options.push_addrs(OPT_SERVER_ID, &[scope.server_id]);
+
+        // NAKs carry nothing else
+        if msg_type == DHCPNAK {
+            return options;
+        }
+
+        // INFORM replies must not carry lease times
+        if msg_type == DHCPOFFER || !yiaddr.is_unspecified() {
+            let lease_time = lock!(pool.leases, leases => leases.lease_time());
+            options.push_u32(OPT_LEASE_TIME, lease_time);
+            options.push_u32(OPT_RENEWAL_TIME, scope.renewal_time.unwrap_or(lease_time / 2));
+            options.push_u32(OPT_REBINDING_TIME, scope.rebinding_time.unwrap_or(lease_time / 8 * 7));
+        }
+
+        options.push_addrs(OPT_SUBNET_MASK, &[pool.mask]);
+        if pool.routers.is_empty() {
+            options.push_addrs(OPT_ROUTER, &[giaddr]);
+        } else {
+            options.push_addrs(OPT_ROUTER, &pool.routers);
+        }
+        options.push_addrs(OPT_DNS_SERVER, &scope.dns_servers);
+        if !scope.domain.is_empty() {
+            options.push(OPT_DOMAIN_NAME, scope.domain.as_bytes());
+        }
+        options.push(OPT_CAPTIVE_PORTAL, scope.captive_portal_uri.as_bytes());
+
+        let hostname = lock!(pool.leases, leases => leases.reservation(mac).and_then(|reservation| reservation.hostname.clone()));
+        if let Some(hostname) = hostname {
+            options.push(OPT_HOSTNAME, hostname.as_bytes());
+        }
+
+        options
+    }
+}
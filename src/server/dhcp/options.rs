@@ -0,0 +1,300 @@
+use std::net::Ipv4Addr;
+
+pub const OPT_PAD: u8 = 0;
+pub const OPT_SUBNET_MASK: u8 = 1;
+pub const OPT_ROUTER: u8 = 3;
+pub const OPT_DNS_SERVER: u8 = 6;
+pub const OPT_HOSTNAME: u8 = 12;
+pub const OPT_DOMAIN_NAME: u8 = 15;
+pub const OPT_REQUESTED_ADDR: u8 = 50;
+pub const OPT_LEASE_TIME: u8 = 51;
+pub const OPT_OVERLOAD: u8 = 52;
+pub const OPT_MESSAGE_TYPE: u8 = 53;
+pub const OPT_SERVER_ID: u8 = 54;
+pub const OPT_PARAMETER_LIST: u8 = 55;
+pub const OPT_MAX_MESSAGE_SIZE: u8 = 57;
+pub const OPT_RENEWAL_TIME: u8 = 58;
+pub const OPT_REBINDING_TIME: u8 = 59;
+pub const OPT_VENDOR_CLASS: u8 = 60;
+pub const OPT_CLIENT_ID: u8 = 61;
+pub const OPT_RELAY_AGENT_INFO: u8 = 82;
+pub const OPT_CAPTIVE_PORTAL: u8 = 114;
+pub const OPT_END: u8 = 255;
+
+// Values of option 52, telling which BOOTP fields carry options too
+const OVERLOAD_FILE: u8 = 1;
+const OVERLOAD_SNAME: u8 = 2;
+
+pub const SNAME_LEN: usize = 64;
+pub const FILE_LEN: usize = 128;
+// Fixed BOOTP header plus the magic cookie
+pub const HEADER_LEN: usize = 240;
+// Every client must accept a 576 byte IP datagram, 28 of which are IP and UDP headers
+pub const MIN_MESSAGE_LEN: usize = 576 - 28;
+
+/// Options of a message, in the order they were added
+#[derive(Clone)]
+pub struct DhcpOptions {
+    options: Vec<(u8, Vec<u8>)>,
+}
+
+/// Encoded options, ready to be copied into a message
+pub struct EncodedOptions {
+    pub options: Vec<u8>,
+    pub file: Option<Vec<u8>>,
+    pub sname: Option<Vec<u8>>,
+    /// Options that did not fit anywhere
+    pub dropped: Vec<u8>,
+}
+
+impl DhcpOptions {
+    pub fn new() -> Self {
+        DhcpOptions { options: Vec::new() }
+    }
+
+    pub fn push(&mut self, code: u8, data: &[u8]) {
+        self.options.push((code, data.to_vec()));
+    }
+
+    /// Adds to an option already present, the way split options are joined back (RFC 3396)
+    pub fn append(&mut self, code: u8, data: &[u8]) {
+        match self.options.iter_mut().find(|(existing, _)| *existing == code) {
+            Some((_, existing)) => existing.extend_from_slice(data),
+            None => self.push(code, data),
+        }
+    }
+
+    pub fn get(&self, code: u8) -> Option<&[u8]> {
+        self.options.iter().find(|(existing, _)| *existing == code).map(|(_, data)| data.as_slice())
+    }
+
+    pub fn get_addr(&self, code: u8) -> Option<Ipv4Addr> {
+        match self.get(code)? {
+            &[a, b, c, d] => Some(Ipv4Addr::new(a, b, c, d)),
+            _ => None,
+        }
+    }
+
+    pub fn push_u32(&mut self, code: u8, value: u32) {
+        self.push(code, &value.to_be_bytes());
+    }
+
+    pub fn push_addrs(&mut self, code: u8, addrs: &[Ipv4Addr]) {
+        let data: Vec<u8> = addrs.iter().flat_map(|addr| addr.octets()).collect();
+        self.push(code, &data);
+    }
+
+    /// Encodes the options for a message of at most `max_len` bytes.
+    ///
+    /// Options longer than 255 bytes are split into several instances of the
+    /// same code, as described in RFC 3396. What does not fit in the options
+    /// field is moved into the `file` and then the `sname` field, announced
+    /// with option 52 (RFC 2132, section 9.3). Options of up to 255 bytes are
+    /// never split, so clients unaware of RFC 3396 still read them correctly.
+    ///
+    /// The relay agent information option always comes last in the options
+    /// field, RFC 3046 section 2.1, its room is kept before anything else.
+    pub fn encode(&self, max_len: usize) -> EncodedOptions {
+        let max_len = max_len.max(MIN_MESSAGE_LEN);
+        let relay_info = self.get(OPT_RELAY_AGENT_INFO);
+        let relay_info_len = relay_info.map_or(0, |data| Self::encoded_len(data.len()));
+        // Room for the options themselves, minus the end option and the relay agent information
+        let room = (max_len - HEADER_LEN - 1).saturating_sub(relay_info_len);
+        let others = self.options.iter().filter(|(code, _)| *code != OPT_RELAY_AGENT_INFO);
+
+        let mut encoded = EncodedOptions {
+            options: Vec::new(),
+            file: None,
+            sname: None,
+            dropped: Vec::new(),
+        };
+
+        let total: usize = others.clone().map(|(_, data)| Self::encoded_len(data.len())).sum();
+        if total <= room {
+            for (code, data) in others {
+                Self::encode_option(&mut encoded.options, *code, data);
+            }
+            if let Some(relay_info) = relay_info {
+                Self::encode_option(&mut encoded.options, OPT_RELAY_AGENT_INFO, relay_info);
+            }
+            encoded.options.push(OPT_END);
+            return encoded;
+        }
+
+        // Overloading: option 52 goes first and each field gets its own end option, keep room for both
+        let mut fields: [(Vec<u8>, usize); 3] = [
+            (Vec::new(), room.saturating_sub(3)),
+            (Vec::new(), FILE_LEN - 1),
+            (Vec::new(), SNAME_LEN - 1),
+        ];
+        for (code, data) in others {
+            if data.len() <= 255 {
+                match fields.iter_mut().find(|(buffer, len)| buffer.len() + data.len() + 2 <= *len) {
+                    Some((buffer, _)) => Self::encode_option(buffer, *code, data),
+                    None => encoded.dropped.push(*code),
+                }
+                continue;
+            }
+
+            // Long options may be cut anywhere, as long as the pieces follow the options, file, sname order
+            let capacity: usize = fields.iter().map(|(buffer, len)| Self::capacity(len - buffer.len())).sum();
+            if capacity < data.len() {
+                encoded.dropped.push(*code);
+                continue;
+            }
+            let mut rest = &data[..];
+            for (buffer, len) in fields.iter_mut() {
+                while !rest.is_empty() && *len - buffer.len() > 2 {
+                    let chunk = rest.len().min(255).min(*len - buffer.len() - 2);
+                    Self::encode_option(buffer, *code, &rest[..chunk]);
+                    rest = &rest[chunk..];
+                }
+            }
+        }
+
+        let [options, file, sname] = fields;
+        let mut overload = 0;
+        if !file.0.is_empty() {
+            overload |= OVERLOAD_FILE;
+            encoded.file = Some(Self::terminate_field(file.0, FILE_LEN));
+        }
+        if !sname.0.is_empty() {
+            overload |= OVERLOAD_SNAME;
+            encoded.sname = Some(Self::terminate_field(sname.0, SNAME_LEN));
+        }
+        if overload != 0 {
+            encoded.options.extend(&[OPT_OVERLOAD, 1, overload]);
+        }
+        encoded.options.extend(options.0);
+        if let Some(relay_info) = relay_info {
+            Self::encode_option(&mut encoded.options, OPT_RELAY_AGENT_INFO, relay_info);
+        }
+        encoded.options.push(OPT_END);
+        encoded
+    }
+
+    fn encoded_len(len: usize) -> usize {
+        // Every started chunk of 255 bytes costs a code and a length byte, empty options still take two
+        len + 2 * len.div_ceil(255).max(1)
+    }
+
+    /// Option payload bytes that fit in `free` bytes, once split into 255 byte chunks
+    fn capacity(free: usize) -> usize {
+        free.saturating_sub(2 * free.div_ceil(257))
+    }
+
+    fn encode_option(buffer: &mut Vec<u8>, code: u8, data: &[u8]) {
+        if data.is_empty() {
+            buffer.extend(&[code, 0]);
+        }
+        for chunk in data.chunks(255) {
+            buffer.push(code);
+            buffer.push(chunk.len() as u8);
+            buffer.extend(chunk);
+        }
+    }
+
+    fn terminate_field(mut field: Vec<u8>, len: usize) -> Vec<u8> {
+        field.push(OPT_END);
+        field.resize(len, OPT_PAD);
+        field
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (code, length) of every option in an encoded field, up to its end option
+    fn instances(field: &[u8]) -> Vec<(u8, usize)> {
+        let mut found = Vec::new();
+        let mut i = 0;
+        while i < field.len() && field[i] != OPT_END {
+            if field[i] == OPT_PAD {
+                i += 1;
+                continue;
+            }
+            found.push((field[i], field[i + 1] as usize));
+            i += 2 + field[i + 1] as usize;
+        }
+        found
+    }
+
+    #[test]
+    fn options_up_to_255_bytes_are_not_split() {
+        let mut options = DhcpOptions::new();
+        options.push(OPT_DOMAIN_NAME, &[b'a'; 255]);
+        let encoded = options.encode(1500);
+        assert_eq!(instances(&encoded.options), vec![(OPT_DOMAIN_NAME, 255)]);
+    }
+
+    #[test]
+    fn long_options_are_split() {
+        let mut options = DhcpOptions::new();
+        options.push(OPT_CAPTIVE_PORTAL, &[b'a'; 600]);
+        let encoded = options.encode(1500);
+        assert_eq!(
+            instances(&encoded.options),
+            vec![(OPT_CAPTIVE_PORTAL, 255), (OPT_CAPTIVE_PORTAL, 255), (OPT_CAPTIVE_PORTAL, 90)]
+        );
+        assert!(encoded.file.is_none() && encoded.sname.is_none() && encoded.dropped.is_empty());
+    }
+
+    #[test]
+    fn split_options_are_joined_back() {
+        let mut options = DhcpOptions::new();
+        options.append(OPT_CAPTIVE_PORTAL, b"http://");
+        options.append(OPT_HOSTNAME, b"host");
+        options.append(OPT_CAPTIVE_PORTAL, b"portal/");
+        assert_eq!(options.get(OPT_CAPTIVE_PORTAL), Some(&b"http://portal/"[..]));
+        assert_eq!(options.get(OPT_HOSTNAME), Some(&b"host"[..]));
+    }
+
+    #[test]
+    fn overflow_goes_to_file_then_sname() {
+        let mut options = DhcpOptions::new();
+        for code in [224, 225, 226] {
+            options.push(code, &[code; 120]);
+        }
+        let encoded = options.encode(MIN_MESSAGE_LEN);
+        let file = encoded.file.expect("options overloaded into file");
+        assert_eq!(file.len(), FILE_LEN);
+        assert_eq!(encoded.options[..3], [OPT_OVERLOAD, 1, OVERLOAD_FILE]);
+        assert!(encoded.sname.is_none());
+        assert!(HEADER_LEN + encoded.options.len() <= MIN_MESSAGE_LEN);
+    }
+
+    #[test]
+    fn relay_agent_information_stays_last_in_the_options_field() {
+        let mut options = DhcpOptions::new();
+        options.push(OPT_MESSAGE_TYPE, &[2]);
+        options.push(OPT_RELAY_AGENT_INFO, &[1, 4, b'e', b't', b'h', b'0']);
+        options.push(OPT_DNS_SERVER, &[10, 9, 0, 1]);
+        let encoded = options.encode(1500);
+        assert_eq!(instances(&encoded.options), vec![(OPT_MESSAGE_TYPE, 1), (OPT_DNS_SERVER, 4), (OPT_RELAY_AGENT_INFO, 6)]);
+
+        // Filling up every field moves or drops the other options, never this one
+        for code in 224..=228 {
+            options.push(code, &[code; 100]);
+        }
+        let encoded = options.encode(MIN_MESSAGE_LEN);
+        assert!(!encoded.dropped.is_empty() && encoded.file.is_some());
+        assert!(!encoded.dropped.contains(&OPT_RELAY_AGENT_INFO));
+        assert_eq!(instances(&encoded.options).last(), Some(&(OPT_RELAY_AGENT_INFO, 6)));
+        for field in [&encoded.file, &encoded.sname].into_iter().flatten() {
+            assert!(instances(field).iter().all(|(code, _)| *code != OPT_RELAY_AGENT_INFO));
+        }
+        assert!(HEADER_LEN + encoded.options.len() <= MIN_MESSAGE_LEN);
+    }
+
+    #[test]
+    fn what_fits_nowhere_is_dropped() {
+        let mut options = DhcpOptions::new();
+        for code in 224..=228 {
+            options.push(code, &[code; 200]);
+        }
+        let encoded = options.encode(MIN_MESSAGE_LEN);
+        assert!(!encoded.dropped.is_empty());
+        assert!(HEADER_LEN + encoded.options.len() <= MIN_MESSAGE_LEN);
+    }
+}
@@ -0,0 +1,367 @@
+use crate::server::dhcp::lease::ClientInfo;
+use crate::server::iface::MacAddr;
+use crate::server::dhcp::options::*;
+use std::fmt;
+use std::net::Ipv4Addr;
+
+pub const BOOTREQUEST: u8 = 1;
+pub const BOOTREPLY: u8 = 2;
+
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const HTYPE_ETHERNET: u8 = 1;
+const HLEN_ETHERNET: u8 = 6;
+// Client wants its replies broadcast, RFC 2131 section 2
+const FLAG_BROADCAST: u16 = 0x8000;
+
+#[derive(Debug, PartialEq)]
+pub enum DhcpError {
+    /// Fewer bytes than the fixed header and magic cookie
+    Truncated(usize),
+    BadMagicCookie,
+    /// Option whose length runs past the end of its field
+    MalformedOption(u8),
+    /// Anything but Ethernet hardware addresses
+    UnsupportedHardware { htype: u8, hlen: u8 },
+}
+
+impl fmt::Display for DhcpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DhcpError::Truncated(len) => write!(f, "truncated packet of {} bytes", len),
+            DhcpError::BadMagicCookie => write!(f, "bad magic cookie"),
+            DhcpError::MalformedOption(code) => write!(f, "malformed option {}", code),
+            DhcpError::UnsupportedHardware { htype, hlen } => {
+                write!(f, "unsupported hardware type {} with address length {}", htype, hlen)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DhcpError {}
+
+/// A DHCP message: the fixed BOOTP header followed by the magic cookie and the options
+#[derive(Clone)]
+pub struct DhcpPacket {
+    pub op: u8,
+    pub htype: u8,
+    pub hlen: u8,
+    pub hops: u8,
+    pub xid: u32,
+    pub secs: u16,
+    pub flags: u16,
+    pub ciaddr: Ipv4Addr,
+    pub yiaddr: Ipv4Addr,
+    pub siaddr: Ipv4Addr,
+    pub giaddr: Ipv4Addr,
+    pub chaddr: [u8; 16],
+    pub sname: [u8; SNAME_LEN],
+    pub file: [u8; FILE_LEN],
+    pub options: DhcpOptions,
+}
+
+impl DhcpPacket {
+    /// Parses a received message.
+    ///
+    /// Options split over several instances (RFC 3396) are joined back
+    /// together, and the `file` and `sname` fields are read for options too
+    /// when option 52 says so.
+    pub fn parse(buffer: &[u8]) -> Result<Self, DhcpError> {
+        if buffer.len() < HEADER_LEN {
+            return Err(DhcpError::Truncated(buffer.len()));
+        }
+        if buffer[236..240] != MAGIC_COOKIE {
+            return Err(DhcpError::BadMagicCookie);
+        }
+        let (htype, hlen) = (buffer[1], buffer[2]);
+        if htype != HTYPE_ETHERNET || hlen != HLEN_ETHERNET {
+            return Err(DhcpError::UnsupportedHardware { htype, hlen });
+        }
+
+        let mut packet = DhcpPacket {
+            op: buffer[0],
+            htype,
+            hlen,
+            hops: buffer[3],
+            xid: u32::from_be_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]),
+            secs: u16::from_be_bytes([buffer[8], buffer[9]]),
+            flags: u16::from_be_bytes([buffer[10], buffer[11]]),
+            ciaddr: Self::read_addr(&buffer[12..16]),
+            yiaddr: Self::read_addr(&buffer[16..20]),
+            siaddr: Self::read_addr(&buffer[20..24]),
+            giaddr: Self::read_addr(&buffer[24..28]),
+            chaddr: [0; 16],
+            sname: [0; SNAME_LEN],
+            file: [0; FILE_LEN],
+            options: DhcpOptions::new(),
+        };
+        packet.chaddr.copy_from_slice(&buffer[28..44]);
+        packet.sname.copy_from_slice(&buffer[44..108]);
+        packet.file.copy_from_slice(&buffer[108..236]);
+
+        Self::parse_options(&buffer[HEADER_LEN..], &mut packet.options)?;
+        let overload = packet.options.get(OPT_OVERLOAD).and_then(|data| data.first().copied()).unwrap_or(0);
+        if overload & 1 != 0 {
+            Self::parse_options(&buffer[108..236], &mut packet.options)?;
+        }
+        if overload & 2 != 0 {
+            Self::parse_options(&buffer[44..108], &mut packet.options)?;
+        }
+
+        Ok(packet)
+    }
+
+    fn read_addr(bytes: &[u8]) -> Ipv4Addr {
+        Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])
+    }
+
+    fn parse_options(buffer: &[u8], options: &mut DhcpOptions) -> Result<(), DhcpError> {
+        let mut i = 0;
+        while i < buffer.len() {
+            let code = buffer[i];
+            if code == OPT_END {
+                break;
+            }
+            if code == OPT_PAD {
+                i += 1;
+                continue;
+            }
+            let len = *buffer.get(i + 1).ok_or(DhcpError::MalformedOption(code))? as usize;
+            let data = buffer.get(i + 2..i + 2 + len).ok_or(DhcpError::MalformedOption(code))?;
+            options.append(code, data);
+            i += 2 + len;
+        }
+        Ok(())
+    }
+
+    /// Starts a reply to this request, echoing the fields the client needs to match it
+    pub fn reply(&self) -> DhcpPacket {
+        DhcpPacket {
+            op: BOOTREPLY,
+            htype: self.htype,
+            hlen: self.hlen,
+            hops: 0,
+            xid: self.xid,
+            secs: 0,
+            flags: self.flags,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: self.giaddr,
+            chaddr: self.chaddr,
+            sname: [0; SNAME_LEN],
+            file: [0; FILE_LEN],
+            options: DhcpOptions::new(),
+        }
+    }
+
+    /// Serializes the message into at most `max_len` bytes, along with the codes of the options that did not fit
+    pub fn serialize(&self, max_len: usize) -> (Vec<u8>, Vec<u8>) {
+        let encoded = self.options.encode(max_len);
+
+        let mut buffer = Vec::with_capacity(HEADER_LEN + encoded.options.len());
+        buffer.extend(&[self.op, self.htype, self.hlen, self.hops]);
+        buffer.extend(&self.xid.to_be_bytes());
+        buffer.extend(&self.secs.to_be_bytes());
+        buffer.extend(&self.flags.to_be_bytes());
+        buffer.extend(&self.ciaddr.octets());
+        buffer.extend(&self.yiaddr.octets());
+        buffer.extend(&self.siaddr.octets());
+        buffer.extend(&self.giaddr.octets());
+        buffer.extend(&self.chaddr);
+        buffer.extend(encoded.sname.as_deref().unwrap_or(&self.sname));
+        buffer.extend(encoded.file.as_deref().unwrap_or(&self.file));
+        buffer.extend(&MAGIC_COOKIE);
+        buffer.extend(&encoded.options);
+
+        (buffer, encoded.dropped)
+    }
+
+    pub fn mac(&self) -> MacAddr {
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(&self.chaddr[..6]);
+        MacAddr(mac)
+    }
+
+    pub fn message_type(&self) -> Option<u8> {
+        self.options.get(OPT_MESSAGE_TYPE).filter(|data| data.len() == 1).map(|data| data[0])
+    }
+
+    pub fn requested_addr(&self) -> Option<Ipv4Addr> {
+        self.options.get_addr(OPT_REQUESTED_ADDR)
+    }
+
+    pub fn server_id(&self) -> Option<Ipv4Addr> {
+        self.options.get_addr(OPT_SERVER_ID)
+    }
+
+    /// Option 61 when the client sent one, its hardware address otherwise
+    pub fn client_id(&self) -> Vec<u8> {
+        match self.options.get(OPT_CLIENT_ID) {
+            Some(client_id) if !client_id.is_empty() => client_id.to_vec(),
+            _ => {
+                let mut client_id = vec![self.htype];
+                client_id.extend_from_slice(&self.chaddr[..self.hlen as usize]);
+                client_id
+            }
+        }
+    }
+
+    /// Hostname, vendor class and parameter request list, as far as the client sent them
+    pub fn client_info(&self) -> ClientInfo {
+        // Hostnames end up in logs, lease files and DNS, so keep only what a hostname may hold
+        let hostname = self.options.get(OPT_HOSTNAME).map(|data| {
+            String::from_utf8_lossy(data)
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_' || *c == '.')
+                .collect::<String>()
+        });
+        let vendor_class = self.options.get(OPT_VENDOR_CLASS).map(|data| {
+            String::from_utf8_lossy(data)
+                .chars()
+                .filter(|c| !c.is_control())
+                .collect::<String>()
+        });
+        ClientInfo {
+            hostname: hostname.filter(|hostname| !hostname.is_empty()),
+            vendor_class: vendor_class.filter(|vendor_class| !vendor_class.is_empty()),
+            parameters: self.options.get(OPT_PARAMETER_LIST).map(|data| data.to_vec()).unwrap_or_default(),
+        }
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.flags & FLAG_BROADCAST != 0
+    }
+
+    pub fn set_broadcast(&mut self) {
+        self.flags |= FLAG_BROADCAST;
+    }
+
+    /// Largest reply the client accepts, without the IP and UDP headers it counts in
+    pub fn max_message_size(&self) -> usize {
+        match self.options.get(OPT_MAX_MESSAGE_SIZE) {
+            Some(&[high, low]) => (u16::from_be_bytes([high, low]) as usize).saturating_sub(28),
+            _ => MIN_MESSAGE_LEN,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A DHCPDISCOVER from 02:00:00:00:00:01 with the given options, end option included
+    fn discover(options: &[u8]) -> Vec<u8> {
+        let mut buffer = vec![0u8; HEADER_LEN];
+        buffer[..4].copy_from_slice(&[BOOTREQUEST, HTYPE_ETHERNET, HLEN_ETHERNET, 0]);
+        buffer[4..8].copy_from_slice(&0x1234_5678u32.to_be_bytes());
+        buffer[28..34].copy_from_slice(&[2, 0, 0, 0, 0, 1]);
+        buffer[236..240].copy_from_slice(&MAGIC_COOKIE);
+        buffer.extend(&[OPT_MESSAGE_TYPE, 1, 1]);
+        buffer.extend(options);
+        buffer.push(OPT_END);
+        buffer
+    }
+
+    fn round_trip(reply: &DhcpPacket, max_len: usize) -> (Vec<u8>, DhcpPacket) {
+        let (buffer, dropped) = reply.serialize(max_len);
+        assert!(dropped.is_empty(), "dropped options {:?}", dropped);
+        assert!(buffer.len() <= max_len.max(MIN_MESSAGE_LEN));
+        let parsed = DhcpPacket::parse(&buffer).unwrap();
+        (buffer, parsed)
+    }
+
+    #[test]
+    fn parses_a_request() {
+        let packet = DhcpPacket::parse(&discover(&[OPT_HOSTNAME, 4, b'h', b'o', b's', b't'])).unwrap();
+        assert_eq!(packet.op, BOOTREQUEST);
+        assert_eq!(packet.xid, 0x1234_5678);
+        assert_eq!(packet.mac().to_string(), "02:00:00:00:00:01");
+        assert_eq!(packet.message_type(), Some(1));
+        assert_eq!(packet.client_info().hostname.as_deref(), Some("host"));
+    }
+
+    #[test]
+    fn long_option_round_trip() {
+        let request = DhcpPacket::parse(&discover(&[])).unwrap();
+        let uri: Vec<u8> = (0..300).map(|i| b'a' + (i % 26) as u8).collect();
+        let mut reply = request.reply();
+        reply.options.push(OPT_MESSAGE_TYPE, &[2]);
+        reply.options.push(OPT_CAPTIVE_PORTAL, &uri);
+
+        let (_, parsed) = round_trip(&reply, 1500);
+        assert_eq!(parsed.options.get(OPT_CAPTIVE_PORTAL), Some(&uri[..]));
+        assert_eq!(parsed.options.get(OPT_MESSAGE_TYPE), Some(&[2][..]));
+        assert_eq!(parsed.xid, request.xid);
+    }
+
+    #[test]
+    fn overloaded_round_trip() {
+        let request = DhcpPacket::parse(&discover(&[])).unwrap();
+        let mut reply = request.reply();
+        reply.options.push(OPT_MESSAGE_TYPE, &[2]);
+        reply.options.push(OPT_DOMAIN_NAME, &[b'd'; 200]);
+        reply.options.push(OPT_VENDOR_CLASS, &[b'v'; 100]);
+        reply.options.push(OPT_CAPTIVE_PORTAL, &[b'c'; 60]);
+        reply.options.push(OPT_HOSTNAME, &[b'h'; 40]);
+
+        // Vendor class ends up in the file field, hostname in sname
+        let (buffer, parsed) = round_trip(&reply, MIN_MESSAGE_LEN);
+        assert_eq!(parsed.options.get(OPT_OVERLOAD), Some(&[3][..]));
+        assert_eq!(buffer[108], OPT_VENDOR_CLASS);
+        assert_eq!(buffer[44], OPT_HOSTNAME);
+        assert_eq!(parsed.options.get(OPT_DOMAIN_NAME), Some(&[b'd'; 200][..]));
+        assert_eq!(parsed.options.get(OPT_VENDOR_CLASS), Some(&[b'v'; 100][..]));
+        assert_eq!(parsed.options.get(OPT_CAPTIVE_PORTAL), Some(&[b'c'; 60][..]));
+        assert_eq!(parsed.options.get(OPT_HOSTNAME), Some(&[b'h'; 40][..]));
+    }
+
+    #[test]
+    fn long_option_split_across_fields_round_trip() {
+        let request = DhcpPacket::parse(&discover(&[])).unwrap();
+        let uri: Vec<u8> = (0..400).map(|i| b'a' + (i % 26) as u8).collect();
+        let mut reply = request.reply();
+        reply.options.push(OPT_MESSAGE_TYPE, &[2]);
+        reply.options.push(OPT_CAPTIVE_PORTAL, &uri);
+
+        let (_, parsed) = round_trip(&reply, MIN_MESSAGE_LEN);
+        assert_eq!(parsed.options.get(OPT_CAPTIVE_PORTAL), Some(&uri[..]));
+    }
+
+    #[test]
+    fn short_packets_are_rejected() {
+        let buffer = discover(&[]);
+        for len in 0..HEADER_LEN {
+            assert_eq!(DhcpPacket::parse(&buffer[..len]).err(), Some(DhcpError::Truncated(len)));
+        }
+    }
+
+    #[test]
+    fn malformed_packets_are_rejected() {
+        let mut buffer = discover(&[]);
+        buffer[236] = 0;
+        assert_eq!(DhcpPacket::parse(&buffer).err(), Some(DhcpError::BadMagicCookie));
+
+        let mut buffer = discover(&[]);
+        buffer[2] = 16;
+        assert_eq!(DhcpPacket::parse(&buffer).err(), Some(DhcpError::UnsupportedHardware { htype: 1, hlen: 16 }));
+
+        // Hostname claiming more bytes than the packet holds
+        let mut buffer = discover(&[]);
+        buffer.pop();
+        buffer.extend(&[OPT_HOSTNAME, 10, b'h']);
+        assert_eq!(DhcpPacket::parse(&buffer).err(), Some(DhcpError::MalformedOption(OPT_HOSTNAME)));
+
+        // Overload pointing at a file field whose last option runs past its end
+        let mut buffer = discover(&[OPT_OVERLOAD, 1, 1]);
+        buffer[235] = OPT_HOSTNAME;
+        assert_eq!(DhcpPacket::parse(&buffer).err(), Some(DhcpError::MalformedOption(OPT_HOSTNAME)));
+    }
+
+    #[test]
+    fn cut_options_do_not_panic() {
+        let buffer = discover(&[OPT_HOSTNAME, 4, b'h', b'o', b's', b't', OPT_PARAMETER_LIST, 3, 1, 3, 6]);
+        for len in HEADER_LEN..buffer.len() {
+            let _ = DhcpPacket::parse(&buffer[..len]);
+        }
+    }
+}
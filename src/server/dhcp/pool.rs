@@ -0,0 +1,62 @@
+use crate::server::dhcp::lease::LeaseTable;
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+
+/// Addresses of one subnet, along with the options that depend on the subnet
+pub struct Pool {
+    network: u32,
+    pub mask: Ipv4Addr,
+    /// Routers handed to clients, empty for a relayed subnet whose relay agent is its router
+    pub routers: Vec<Ipv4Addr>,
+    pub leases: Mutex<LeaseTable>,
+}
+
+impl Pool {
+    /// Creates a pool from `start` to `end`, never handing out the network, broadcast or `excluded` addresses
+    pub fn new(start: Ipv4Addr, end: Ipv4Addr, mask: Ipv4Addr, routers: Vec<Ipv4Addr>, mut excluded: Vec<Ipv4Addr>, lease_time: u32) -> Self {
+        let network = u32::from(start) & u32::from(mask);
+        let broadcast = network | !u32::from(mask);
+        excluded.push(Ipv4Addr::from(network));
+        excluded.push(Ipv4Addr::from(broadcast));
+
+        Pool {
+            network,
+            mask,
+            routers,
+            leases: Mutex::new(LeaseTable::new(start, end, excluded, lease_time)),
+        }
+    }
+
+    /// Parses a pool served through relay agents, written as `<start>-<end>/<mask> [router]`
+    pub fn parse_relayed(entry: &str, lease_time: u32) -> Result<Self, String> {
+        let invalid = || format!("Invalid relay pool: {}", entry);
+        let fields: Vec<&str> = entry.split_whitespace().collect();
+        if fields.is_empty() || fields.len() > 2 {
+            return Err(invalid());
+        }
+
+        let (range, mask) = fields[0].split_once('/').ok_or_else(invalid)?;
+        let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+        let start: Ipv4Addr = start.parse().map_err(|_| invalid())?;
+        let end: Ipv4Addr = end.parse().map_err(|_| invalid())?;
+        let mask: Ipv4Addr = mask.parse().map_err(|_| invalid())?;
+        if u32::from(start) & u32::from(mask) != u32::from(end) & u32::from(mask) || start > end {
+            return Err(format!("{} and {} are not in the same subnet", start, end));
+        }
+        let routers = match fields.get(1) {
+            Some(router) => vec![router.parse().map_err(|_| invalid())?],
+            None => Vec::new(),
+        };
+
+        Ok(Pool::new(start, end, mask, routers, Vec::new(), lease_time))
+    }
+
+    pub fn network(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.network)
+    }
+
+    /// Whether `addr` lies in the pool's subnet
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        u32::from(addr) & u32::from(self.mask) == self.network
+    }
+}
@@ -0,0 +1,133 @@
+use crate::server::iface::MacAddr;
+use libc::{c_int, c_void, sockaddr, sockaddr_ll, socklen_t};
+use std::io::{Error, ErrorKind, Result};
+use std::mem;
+use std::net::Ipv4Addr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+const ETH_P_ARP: u16 = 0x0806;
+const ETH_P_IP: u16 = 0x0800;
+const ARP_REQUEST: u16 = 1;
+const ARP_REPLY: u16 = 2;
+const ARP_LEN: usize = 28;
+
+/// Asks the AP segment whether anyone already uses an address before we hand it out.
+///
+/// A broadcast ARP request is sent for the address without waiting for an
+/// answer. Any ARP packet claiming the address that comes in before the
+/// caller stops listening counts as one.
+pub struct ArpProber {
+    fd: OwnedFd,
+    ifindex: i32,
+    mac: MacAddr,
+    addr: Ipv4Addr,
+}
+
+impl ArpProber {
+    /// Opens the prober on the interface with the given index, `mac` and `addr` being our own
+    pub fn open(ifindex: i32, mac: MacAddr, addr: Ipv4Addr) -> Result<Self> {
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_DGRAM | libc::SOCK_NONBLOCK, ETH_P_ARP.to_be() as c_int) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // Only hear ARP from the AP interface
+        let link_addr = Self::link_addr(ifindex, &MacAddr([0; 6]));
+        let ret = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &link_addr as *const sockaddr_ll as *const sockaddr,
+                mem::size_of::<sockaddr_ll>() as socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(ArpProber { fd, ifindex, mac, addr })
+    }
+
+    fn link_addr(ifindex: i32, mac: &MacAddr) -> sockaddr_ll {
+        let mut addr: sockaddr_ll = unsafe { mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as u16;
+        addr.sll_protocol = ETH_P_ARP.to_be();
+        addr.sll_ifindex = ifindex;
+        addr.sll_halen = 6;
+        addr.sll_addr[..6].copy_from_slice(&mac.0);
+        addr
+    }
+
+    /// Broadcasts an ARP request for `target`, whoever answers shows up in `claims`
+    pub fn send_probe(&self, target: Ipv4Addr) -> Result<()> {
+        let request = self.build_request(target);
+        let broadcast = Self::link_addr(self.ifindex, &MacAddr([0xff; 6]));
+        let sent = unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                request.as_ptr() as *const c_void,
+                request.len(),
+                0,
+                &broadcast as *const sockaddr_ll as *const sockaddr,
+                mem::size_of::<sockaddr_ll>() as socklen_t,
+            )
+        };
+        if sent < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Addresses claimed by the ARP packets received since the last call, with the hardware address claiming them
+    pub fn claims(&self) -> Result<Vec<(Ipv4Addr, MacAddr)>> {
+        let mut buffer = [0u8; 64];
+        let mut claims = Vec::new();
+        while let Some(len) = self.recv(&mut buffer)? {
+            claims.extend(Self::parse_claim(&buffer[..len]));
+        }
+        Ok(claims)
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> Result<Option<usize>> {
+        let len = unsafe { libc::recv(self.fd.as_raw_fd(), buffer.as_mut_ptr() as *mut c_void, buffer.len(), 0) };
+        if len < 0 {
+            let e = Error::last_os_error();
+            if e.kind() == ErrorKind::WouldBlock {
+                return Ok(None);
+            }
+            return Err(e);
+        }
+        Ok(Some(len as usize))
+    }
+
+    fn build_request(&self, target: Ipv4Addr) -> Vec<u8> {
+        let mut request = Vec::with_capacity(ARP_LEN);
+        request.extend(&1u16.to_be_bytes()); // Ethernet
+        request.extend(&ETH_P_IP.to_be_bytes());
+        request.extend(&[6, 4]);
+        request.extend(&ARP_REQUEST.to_be_bytes());
+        request.extend(&self.mac.0);
+        request.extend(&self.addr.octets());
+        request.extend(&[0; 6]);
+        request.extend(&target.octets());
+        request
+    }
+
+    // The address a reply or an announcement is for, or the one a probe asks about
+    fn parse_claim(packet: &[u8]) -> Option<(Ipv4Addr, MacAddr)> {
+        if packet.len() < ARP_LEN || packet[4] != 6 || packet[5] != 4 {
+            return None;
+        }
+        let op = u16::from_be_bytes([packet[6], packet[7]]);
+        let sender_ip = Ipv4Addr::new(packet[14], packet[15], packet[16], packet[17]);
+        let target_ip = Ipv4Addr::new(packet[24], packet[25], packet[26], packet[27]);
+        let claimed = match op {
+            ARP_REQUEST if sender_ip.is_unspecified() => target_ip,
+            ARP_REPLY | ARP_REQUEST if !sender_ip.is_unspecified() => sender_ip,
+            _ => return None,
+        };
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(&packet[8..14]);
+        Some((claimed, MacAddr(mac)))
+    }
+}
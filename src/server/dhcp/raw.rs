@@ -0,0 +1,207 @@
+use crate::server::iface::MacAddr;
+use libc::{c_int, c_void, sockaddr, sockaddr_ll, socklen_t};
+use std::io::{Error, Result};
+use std::mem;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+const ETH_P_IP: u16 = 0x0800;
+const IPPROTO_UDP: u8 = 17;
+
+/// Asks for IP_PKTINFO on every datagram the socket receives, naming the interface it came in on
+pub fn enable_pktinfo(socket: &UdpSocket) -> Result<()> {
+    let enable: c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_PKTINFO,
+            &enable as *const c_int as *const c_void,
+            mem::size_of::<c_int>() as socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Room for a single IP_PKTINFO control message
+const PKTINFO_CMSG_SPACE: usize = 64;
+
+/// Receives a datagram along with the index of the interface it arrived on, see `enable_pktinfo`
+pub fn recv_with_iface(socket: &UdpSocket, buffer: &mut [u8]) -> Result<(usize, SocketAddr, i32)> {
+    let mut src: libc::sockaddr_in = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut c_void,
+        iov_len: buffer.len(),
+    };
+    let mut control = [0u8; PKTINFO_CMSG_SPACE];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut src as *mut libc::sockaddr_in as *mut c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_in>() as socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = control.len();
+
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if len < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let mut ifindex = 0;
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let header = unsafe { &*cmsg };
+        if header.cmsg_level == libc::IPPROTO_IP && header.cmsg_type == libc::IP_PKTINFO {
+            let pktinfo = unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in_pktinfo) };
+            ifindex = pktinfo.ipi_ifindex;
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+
+    let addr = SocketAddrV4::new(Ipv4Addr::from(u32::from_be(src.sin_addr.s_addr)), u16::from_be(src.sin_port));
+    Ok((len as usize, SocketAddr::V4(addr), ifindex))
+}
+
+/// Sends a datagram from `src` out of the interface with the given index, or wherever the route points if it is 0
+pub fn send_with_iface(socket: &UdpSocket, buffer: &[u8], dst: SocketAddrV4, ifindex: i32, src: Ipv4Addr) -> Result<usize> {
+    let mut dst_addr: libc::sockaddr_in = unsafe { mem::zeroed() };
+    dst_addr.sin_family = libc::AF_INET as libc::sa_family_t;
+    dst_addr.sin_port = dst.port().to_be();
+    dst_addr.sin_addr.s_addr = u32::from(*dst.ip()).to_be();
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_ptr() as *mut c_void,
+        iov_len: buffer.len(),
+    };
+
+    let mut control = [0u8; PKTINFO_CMSG_SPACE];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut dst_addr as *mut libc::sockaddr_in as *mut c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_in>() as socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = unsafe { libc::CMSG_SPACE(mem::size_of::<libc::in_pktinfo>() as u32) } as usize;
+
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::IPPROTO_IP;
+        (*cmsg).cmsg_type = libc::IP_PKTINFO;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<libc::in_pktinfo>() as u32) as usize;
+        let pktinfo = libc::in_pktinfo {
+            ipi_ifindex: ifindex,
+            ipi_spec_dst: libc::in_addr { s_addr: u32::from(src).to_be() },
+            ipi_addr: libc::in_addr { s_addr: 0 },
+        };
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::in_pktinfo, pktinfo);
+    }
+
+    let sent = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) };
+    if sent < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(sent as usize)
+}
+
+/// Link layer socket for unicasting UDP datagrams to clients that have no address yet.
+///
+/// The kernel cannot ARP for an address the client does not own yet, so the
+/// IP and UDP headers are built here and the frame is sent straight to the
+/// client's hardware address.
+pub struct PacketSocket {
+    fd: OwnedFd,
+    ifindex: i32,
+}
+
+impl PacketSocket {
+    pub fn open(ifindex: i32) -> Result<Self> {
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_DGRAM, ETH_P_IP.to_be() as c_int) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(PacketSocket {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            ifindex,
+        })
+    }
+
+    pub fn send_udp(&self, src: SocketAddrV4, dst: SocketAddrV4, dst_mac: &MacAddr, payload: &[u8]) -> Result<usize> {
+        let packet = Self::build_udp_packet(src, dst, payload);
+
+        let mut addr: sockaddr_ll = unsafe { mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as u16;
+        addr.sll_protocol = ETH_P_IP.to_be();
+        addr.sll_ifindex = self.ifindex;
+        addr.sll_halen = 6;
+        addr.sll_addr[..6].copy_from_slice(&dst_mac.0);
+
+        let sent = unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                packet.as_ptr() as *const c_void,
+                packet.len(),
+                0,
+                &addr as *const sockaddr_ll as *const sockaddr,
+                mem::size_of::<sockaddr_ll>() as socklen_t,
+            )
+        };
+        if sent < 0 {
+            return Err(Error::last_os_error());
+        }
+        // Report the payload, not the headers we added
+        Ok((sent as usize).saturating_sub(packet.len() - payload.len()))
+    }
+
+    fn build_udp_packet(src: SocketAddrV4, dst: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+        let udp_len = 8 + payload.len();
+        let total_len = 20 + udp_len;
+
+        let mut packet = Vec::with_capacity(total_len);
+        packet.extend(&[0x45, 0]); // IPv4, 20 byte header, no TOS
+        packet.extend(&(total_len as u16).to_be_bytes());
+        packet.extend(&[0, 0, 0, 0]); // Identification, flags and fragment offset
+        packet.extend(&[64, IPPROTO_UDP, 0, 0]); // TTL, protocol, checksum filled in below
+        packet.extend(&src.ip().octets());
+        packet.extend(&dst.ip().octets());
+        let checksum = Self::checksum(&packet[..20], 0);
+        packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        packet.extend(&src.port().to_be_bytes());
+        packet.extend(&dst.port().to_be_bytes());
+        packet.extend(&(udp_len as u16).to_be_bytes());
+        packet.extend(&[0, 0]);
+        packet.extend(payload);
+        let checksum = Self::checksum(&packet[20..], Self::pseudo_header_sum(*src.ip(), *dst.ip(), udp_len));
+        // An all zero UDP checksum means none was computed, send all ones instead
+        let checksum = if checksum == 0 { 0xffff } else { checksum };
+        packet[26..28].copy_from_slice(&checksum.to_be_bytes());
+
+        packet
+    }
+
+    fn pseudo_header_sum(src: Ipv4Addr, dst: Ipv4Addr, udp_len: usize) -> u32 {
+        let mut pseudo = Vec::with_capacity(12);
+        pseudo.extend(&src.octets());
+        pseudo.extend(&dst.octets());
+        pseudo.extend(&[0, IPPROTO_UDP]);
+        pseudo.extend(&(udp_len as u16).to_be_bytes());
+        Self::sum_words(&pseudo)
+    }
+
+    fn sum_words(data: &[u8]) -> u32 {
+        data.chunks(2)
+            .map(|word| u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32)
+            .sum()
+    }
+
+    /// Internet checksum (RFC 1071) of `data`, starting from a partial sum
+    fn checksum(data: &[u8], initial: u32) -> u16 {
+        let mut sum = initial + Self::sum_words(data);
+        while sum >> 16 != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+}
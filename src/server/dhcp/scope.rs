@@ -0,0 +1,45 @@
+use crate::lock;
+use crate::server::dhcp::lease::Reservation;
+use crate::server::iface::MacAddr;
+use crate::server::dhcp::pool::Pool;
+use crate::server::dhcp::probe::ArpProber;
+use crate::server::dhcp::raw::PacketSocket;
+use std::net::Ipv4Addr;
+
+/// Clients served on one interface: the interface's subnet, the subnets
+/// relayed to it and the options they are handed
+pub struct Scope {
+    pub name: String,
+    pub iface: String,
+    /// Our address on the interface
+    pub addr: Ipv4Addr,
+    pub server_id: Ipv4Addr,
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub domain: String,
+    pub renewal_time: Option<u32>,
+    pub rebinding_time: Option<u32>,
+    pub captive_portal_uri: String,
+    /// The interface's own subnet
+    pub pool: Pool,
+    /// Subnets behind relay agents, picked by giaddr
+    pub relay_pools: Vec<Pool>,
+}
+
+impl Scope {
+    pub fn pools(&self) -> impl Iterator<Item = &Pool> {
+        std::iter::once(&self.pool).chain(self.relay_pools.iter())
+    }
+
+    /// The client's reservation in any pool of the scope, along with that pool
+    pub fn reservation(&self, mac: &MacAddr) -> Option<(&Pool, Reservation)> {
+        self.pools()
+            .find_map(|pool| lock!(pool.leases, leases => leases.reservation(mac).cloned()).map(|reservation| (pool, reservation)))
+    }
+}
+
+/// Sockets for reaching the clients of a scope directly on its interface, opened once the server runs
+pub struct ScopeLink {
+    pub ifindex: i32,
+    pub packet_socket: Option<PacketSocket>,
+    pub prober: Option<ArpProber>,
+}
@@ -0,0 +1,236 @@
+use crate::server::dhcp::lease::{now, LeaseState};
+use std::collections::HashMap;
+use std::net::Ipv6Addr;
+
+// How long an advertised address is held for a client that has not requested it yet
+const OFFER_HOLD_TIME: u64 = 60;
+
+#[derive(Clone, Debug)]
+pub struct Lease {
+    pub duid: Vec<u8>,
+    pub iaid: u32,
+    pub addr: Ipv6Addr,
+    pub expires: u64,
+    pub state: LeaseState,
+}
+
+/// Leases handed out from the DHCPv6 pool, keyed by client DUID and IAID.
+///
+/// Like the IPv4 lease table, expired leases are kept so a returning client
+/// gets its previous address back, and are only recycled once the pool has
+/// no never-used address left.
+pub struct LeaseTable {
+    pool_start: u128,
+    pool_end: u128,
+    lease_time: u32,
+    leases: HashMap<(Vec<u8>, u32), Lease>,
+}
+
+impl LeaseTable {
+    pub fn new(pool_start: Ipv6Addr, pool_end: Ipv6Addr, lease_time: u32) -> Self {
+        LeaseTable {
+            pool_start: u128::from(pool_start),
+            pool_end: u128::from(pool_end),
+            lease_time,
+            leases: HashMap::new(),
+        }
+    }
+
+    pub fn lease_time(&self) -> u32 {
+        self.lease_time
+    }
+
+    fn in_pool(&self, addr: Ipv6Addr) -> bool {
+        (self.pool_start..=self.pool_end).contains(&u128::from(addr))
+    }
+
+    fn is_free_for(&self, addr: Ipv6Addr, key: &(Vec<u8>, u32)) -> bool {
+        self.in_pool(addr) && self.leases.iter().all(|(owner, lease)| owner == key || lease.addr != addr)
+    }
+
+    // The address the IA holds or held last, the one it asks for, or any free one
+    fn pick(&self, key: &(Vec<u8>, u32), hint: Option<Ipv6Addr>) -> Option<Ipv6Addr> {
+        match self.leases.get(key) {
+            Some(lease) if self.is_free_for(lease.addr, key) => Some(lease.addr),
+            _ => match hint {
+                Some(addr) if self.is_free_for(addr, key) => Some(addr),
+                _ => self.next_free(),
+            },
+        }
+    }
+
+    fn next_free(&self) -> Option<Ipv6Addr> {
+        // Only as many addresses as there are leases can be taken, so this stops early even in a /64
+        let unused = (self.pool_start..=self.pool_end)
+            .map(Ipv6Addr::from)
+            .find(|addr| self.leases.values().all(|lease| lease.addr != *addr));
+        if unused.is_some() {
+            return unused;
+        }
+
+        // Pool exhausted, recycle the address that expired the longest time ago
+        self.leases
+            .values()
+            .filter(|lease| lease.state == LeaseState::Expired)
+            .min_by_key(|lease| lease.expires)
+            .map(|lease| lease.addr)
+    }
+
+    fn store(&mut self, key: (Vec<u8>, u32), addr: Ipv6Addr, state: LeaseState, expires: u64) -> Lease {
+        // Recycling an expired lease of another client takes it away from them
+        self.leases.retain(|owner, lease| *owner == key || lease.addr != addr);
+        let lease = Lease {
+            duid: key.0.clone(),
+            iaid: key.1,
+            addr,
+            expires,
+            state,
+        };
+        self.leases.insert(key, lease.clone());
+        lease
+    }
+
+    /// Picks an address for a Solicit and holds it for a short while
+    pub fn offer(&mut self, duid: &[u8], iaid: u32, hint: Option<Ipv6Addr>) -> Option<Ipv6Addr> {
+        let key = (duid.to_vec(), iaid);
+        let addr = self.pick(&key, hint)?;
+        match self.leases.get(&key) {
+            Some(lease) if lease.state == LeaseState::Bound && lease.addr == addr => {}
+            _ => {
+                self.store(key, addr, LeaseState::Offered, now() + OFFER_HOLD_TIME);
+            }
+        }
+        Some(addr)
+    }
+
+    /// Binds an address to the IA for a Request, or a rapid-commit Solicit
+    pub fn bind(&mut self, duid: &[u8], iaid: u32, hint: Option<Ipv6Addr>) -> Option<Lease> {
+        let key = (duid.to_vec(), iaid);
+        let addr = self.pick(&key, hint)?;
+        let expires = now() + self.lease_time as u64;
+        Some(self.store(key, addr, LeaseState::Bound, expires))
+    }
+
+    /// Extends the IA's lease for a Renew or Rebind, None if the IA holds no address right now.
+    ///
+    /// A lease that expired or was released is not brought back, RFC 8415 section 18.3.4.
+    pub fn renew(&mut self, duid: &[u8], iaid: u32) -> Option<Lease> {
+        let key = (duid.to_vec(), iaid);
+        match self.leases.get(&key) {
+            Some(lease) if lease.state == LeaseState::Bound && lease.expires > now() => {
+                let addr = lease.addr;
+                let expires = now() + self.lease_time as u64;
+                Some(self.store(key, addr, LeaseState::Bound, expires))
+            }
+            _ => None,
+        }
+    }
+
+    /// Ends the IA's lease early but remembers the address for it, returns the lease as it was before
+    pub fn release(&mut self, duid: &[u8], iaid: u32) -> Option<Lease> {
+        match self.leases.get_mut(&(duid.to_vec(), iaid)) {
+            Some(lease) if lease.state == LeaseState::Bound => {
+                let released = lease.clone();
+                lease.state = LeaseState::Expired;
+                lease.expires = now();
+                Some(released)
+            }
+            _ => None,
+        }
+    }
+
+    /// Expires every lease whose time is up and returns the bound ones that just lapsed
+    pub fn expire(&mut self) -> Vec<Lease> {
+        let now = now();
+        let mut expired = Vec::new();
+
+        self.leases.retain(|_, lease| !(lease.state == LeaseState::Offered && lease.expires <= now));
+        for lease in self.leases.values_mut() {
+            if lease.state == LeaseState::Bound && lease.expires <= now {
+                lease.state = LeaseState::Expired;
+                expired.push(lease.clone());
+            }
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUID: &[u8] = &[0, 3, 0, 1, 0xaa, 0xbb, 0xcc, 0, 0, 1];
+    const OTHER_DUID: &[u8] = &[0, 3, 0, 1, 0xaa, 0xbb, 0xcc, 0, 0, 2];
+
+    fn addr(s: &str) -> Ipv6Addr {
+        s.parse().unwrap()
+    }
+
+    fn table() -> LeaseTable {
+        LeaseTable::new(addr("fd00::10"), addr("fd00::11"), 3600)
+    }
+
+    #[test]
+    fn solicit_then_request() {
+        let mut table = table();
+        let advertised = table.offer(DUID, 1, None).unwrap();
+        assert_eq!(advertised, addr("fd00::10"));
+        // Held for the client, another one gets the next address
+        assert_eq!(table.offer(OTHER_DUID, 1, Some(advertised)), Some(addr("fd00::11")));
+
+        let lease = table.bind(DUID, 1, None).unwrap();
+        assert_eq!(lease.addr, advertised);
+        assert_eq!(lease.state, LeaseState::Bound);
+        assert!(lease.expires >= now() + 3600 - 1);
+
+        // A second IA of the same client is a lease of its own
+        assert_eq!(table.offer(DUID, 2, None), None);
+    }
+
+    #[test]
+    fn renew() {
+        let mut table = table();
+        assert!(table.renew(DUID, 1).is_none());
+
+        let lease = table.bind(DUID, 1, None).unwrap();
+        table.leases.get_mut(&(DUID.to_vec(), 1)).unwrap().expires = now() + 1;
+        let renewed = table.renew(DUID, 1).unwrap();
+        assert_eq!(renewed.addr, lease.addr);
+        assert!(renewed.expires >= now() + 3600 - 1);
+        assert!(table.renew(DUID, 2).is_none());
+        assert!(table.renew(OTHER_DUID, 1).is_none());
+    }
+
+    #[test]
+    fn release() {
+        let mut table = table();
+        let lease = table.bind(DUID, 1, None).unwrap();
+        assert!(table.release(OTHER_DUID, 1).is_none());
+        assert_eq!(table.release(DUID, 1).unwrap().addr, lease.addr);
+        assert!(table.release(DUID, 1).is_none());
+
+        // Released leases are not renewed, but the address waits for the client
+        assert!(table.renew(DUID, 1).is_none());
+        assert_eq!(table.offer(OTHER_DUID, 1, Some(lease.addr)), Some(addr("fd00::11")));
+        assert_eq!(table.bind(DUID, 1, None).unwrap().addr, lease.addr);
+    }
+
+    #[test]
+    fn expiry() {
+        let mut table = table();
+        table.offer(DUID, 1, None).unwrap();
+        table.bind(OTHER_DUID, 1, None).unwrap();
+        for lease in table.leases.values_mut() {
+            lease.expires = now() - 1;
+        }
+        let expired = table.expire();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].duid, OTHER_DUID);
+        assert!(!table.leases.contains_key(&(DUID.to_vec(), 1)));
+
+        // Never-used addresses go first, then the expired lease is recycled
+        assert_eq!(table.offer(DUID, 1, None), Some(addr("fd00::10")));
+        assert_eq!(table.offer(DUID, 2, None), Some(addr("fd00::11")));
+        assert!(!table.leases.contains_key(&(OTHER_DUID.to_vec(), 1)));
+    }
+}
@@ -0,0 +1,353 @@
+use std::fmt;
+use std::net::Ipv6Addr;
+
+// Message types, RFC 8415 section 7.3
+pub const SOLICIT: u8 = 1;
+pub const ADVERTISE: u8 = 2;
+pub const REQUEST: u8 = 3;
+pub const RENEW: u8 = 5;
+pub const REBIND: u8 = 6;
+pub const REPLY: u8 = 7;
+pub const RELEASE: u8 = 8;
+pub const INFORMATION_REQUEST: u8 = 11;
+pub const RELAY_FORW: u8 = 12;
+
+pub const OPT_CLIENTID: u16 = 1;
+pub const OPT_SERVERID: u16 = 2;
+pub const OPT_IA_NA: u16 = 3;
+pub const OPT_IAADDR: u16 = 5;
+pub const OPT_ORO: u16 = 6;
+pub const OPT_STATUS_CODE: u16 = 13;
+pub const OPT_RAPID_COMMIT: u16 = 14;
+pub const OPT_DNS_SERVERS: u16 = 23;
+pub const OPT_CAPTIVE_PORTAL: u16 = 103;
+
+pub const STATUS_SUCCESS: u16 = 0;
+pub const STATUS_NO_ADDRS_AVAIL: u16 = 2;
+pub const STATUS_NO_BINDING: u16 = 3;
+
+// Message type and transaction ID
+const HEADER_LEN: usize = 4;
+// IAID, T1 and T2
+const IA_NA_LEN: usize = 12;
+// Address, preferred and valid lifetime
+const IAADDR_LEN: usize = 24;
+
+#[derive(Debug, PartialEq)]
+pub enum Dhcp6Error {
+    /// Fewer bytes than the message or option header
+    Truncated(usize),
+    /// Option whose length runs past the end of the message or the option it is nested in
+    MalformedOption(u16),
+}
+
+impl fmt::Display for Dhcp6Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Dhcp6Error::Truncated(len) => write!(f, "truncated message of {} bytes", len),
+            Dhcp6Error::MalformedOption(code) => write!(f, "malformed option {}", code),
+        }
+    }
+}
+
+impl std::error::Error for Dhcp6Error {}
+
+/// Options of a message or of an IA, in the order they were added
+pub struct Dhcp6Options {
+    options: Vec<(u16, Vec<u8>)>,
+}
+
+impl Dhcp6Options {
+    pub fn new() -> Self {
+        Dhcp6Options { options: Vec::new() }
+    }
+
+    /// Reads options up to the end of `buffer`, which is also how options nest inside others
+    pub fn parse(buffer: &[u8]) -> Result<Self, Dhcp6Error> {
+        let mut options = Dhcp6Options::new();
+        let mut i = 0;
+        while i < buffer.len() {
+            let header = buffer.get(i..i + 4).ok_or(Dhcp6Error::Truncated(buffer.len()))?;
+            let code = u16::from_be_bytes([header[0], header[1]]);
+            let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+            let data = buffer.get(i + 4..i + 4 + len).ok_or(Dhcp6Error::MalformedOption(code))?;
+            options.push(code, data);
+            i += 4 + len;
+        }
+        Ok(options)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        for (code, data) in &self.options {
+            buffer.extend(&code.to_be_bytes());
+            buffer.extend(&(data.len() as u16).to_be_bytes());
+            buffer.extend(data);
+        }
+        buffer
+    }
+
+    pub fn push(&mut self, code: u16, data: &[u8]) {
+        self.options.push((code, data.to_vec()));
+    }
+
+    pub fn push_status(&mut self, status: u16, message: &str) {
+        let mut data = status.to_be_bytes().to_vec();
+        data.extend(message.as_bytes());
+        self.push(OPT_STATUS_CODE, &data);
+    }
+
+    pub fn get(&self, code: u16) -> Option<&[u8]> {
+        self.get_all(code).next()
+    }
+
+    pub fn get_all(&self, code: u16) -> impl Iterator<Item = &[u8]> {
+        self.options.iter().filter(move |(existing, _)| *existing == code).map(|(_, data)| data.as_slice())
+    }
+
+    pub fn contains(&self, code: u16) -> bool {
+        self.get(code).is_some()
+    }
+}
+
+/// An address inside an IA_NA option
+pub struct IaAddr {
+    pub addr: Ipv6Addr,
+    pub preferred_lifetime: u32,
+    pub valid_lifetime: u32,
+}
+
+/// Identity association for non-temporary addresses, RFC 8415 section 21.4
+pub struct IaNa {
+    pub iaid: u32,
+    pub t1: u32,
+    pub t2: u32,
+    pub addrs: Vec<IaAddr>,
+    pub status: Option<(u16, String)>,
+}
+
+impl IaNa {
+    pub fn new(iaid: u32) -> Self {
+        IaNa {
+            iaid,
+            t1: 0,
+            t2: 0,
+            addrs: Vec::new(),
+            status: None,
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, Dhcp6Error> {
+        if data.len() < IA_NA_LEN {
+            return Err(Dhcp6Error::MalformedOption(OPT_IA_NA));
+        }
+        let read_u32 = |at: usize| u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        let mut ia = IaNa {
+            iaid: read_u32(0),
+            t1: read_u32(4),
+            t2: read_u32(8),
+            addrs: Vec::new(),
+            status: None,
+        };
+
+        let options = Dhcp6Options::parse(&data[IA_NA_LEN..])?;
+        for addr in options.get_all(OPT_IAADDR) {
+            if addr.len() < IAADDR_LEN {
+                return Err(Dhcp6Error::MalformedOption(OPT_IAADDR));
+            }
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addr[..16]);
+            ia.addrs.push(IaAddr {
+                addr: Ipv6Addr::from(octets),
+                preferred_lifetime: u32::from_be_bytes([addr[16], addr[17], addr[18], addr[19]]),
+                valid_lifetime: u32::from_be_bytes([addr[20], addr[21], addr[22], addr[23]]),
+            });
+        }
+        ia.status = options
+            .get(OPT_STATUS_CODE)
+            .filter(|status| status.len() >= 2)
+            .map(|status| (u16::from_be_bytes([status[0], status[1]]), String::from_utf8_lossy(&status[2..]).into_owned()));
+        Ok(ia)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(IA_NA_LEN);
+        data.extend(&self.iaid.to_be_bytes());
+        data.extend(&self.t1.to_be_bytes());
+        data.extend(&self.t2.to_be_bytes());
+
+        let mut options = Dhcp6Options::new();
+        for addr in &self.addrs {
+            let mut iaaddr = addr.addr.octets().to_vec();
+            iaaddr.extend(&addr.preferred_lifetime.to_be_bytes());
+            iaaddr.extend(&addr.valid_lifetime.to_be_bytes());
+            options.push(OPT_IAADDR, &iaaddr);
+        }
+        if let Some((status, message)) = &self.status {
+            options.push_status(*status, message);
+        }
+        data.extend(options.encode());
+        data
+    }
+}
+
+/// A DHCPv6 client/server message, RFC 8415 section 8
+pub struct Message {
+    pub msg_type: u8,
+    pub transaction_id: [u8; 3],
+    pub options: Dhcp6Options,
+}
+
+impl Message {
+    pub fn parse(buffer: &[u8]) -> Result<Self, Dhcp6Error> {
+        if buffer.len() < HEADER_LEN {
+            return Err(Dhcp6Error::Truncated(buffer.len()));
+        }
+        Ok(Message {
+            msg_type: buffer[0],
+            transaction_id: [buffer[1], buffer[2], buffer[3]],
+            options: Dhcp6Options::parse(&buffer[HEADER_LEN..])?,
+        })
+    }
+
+    /// Starts a reply to this message, echoing its transaction ID
+    pub fn reply(&self, msg_type: u8) -> Message {
+        Message {
+            msg_type,
+            transaction_id: self.transaction_id,
+            options: Dhcp6Options::new(),
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buffer = vec![self.msg_type];
+        buffer.extend(&self.transaction_id);
+        buffer.extend(self.options.encode());
+        buffer
+    }
+
+    pub fn client_id(&self) -> Option<&[u8]> {
+        self.options.get(OPT_CLIENTID).filter(|duid| !duid.is_empty())
+    }
+
+    pub fn server_id(&self) -> Option<&[u8]> {
+        self.options.get(OPT_SERVERID)
+    }
+
+    pub fn ia_nas(&self) -> Result<Vec<IaNa>, Dhcp6Error> {
+        self.options.get_all(OPT_IA_NA).map(IaNa::parse).collect()
+    }
+
+    /// Whether the client listed `code` in its option request option
+    pub fn requests(&self, code: u16) -> bool {
+        self.options
+            .get(OPT_ORO)
+            .is_some_and(|oro| oro.chunks_exact(2).any(|requested| u16::from_be_bytes([requested[0], requested[1]]) == code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ia_na(iaid: u32, addr: Option<Ipv6Addr>) -> Vec<u8> {
+        let mut ia = IaNa::new(iaid);
+        ia.t1 = 1800;
+        ia.t2 = 2880;
+        ia.addrs.extend(addr.map(|addr| IaAddr {
+            addr,
+            preferred_lifetime: 3600,
+            valid_lifetime: 3600,
+        }));
+        ia.encode()
+    }
+
+    fn solicit() -> Vec<u8> {
+        let mut message = Message {
+            msg_type: SOLICIT,
+            transaction_id: [1, 2, 3],
+            options: Dhcp6Options::new(),
+        };
+        message.options.push(OPT_CLIENTID, &[0, 3, 0, 1, 0xaa, 0xbb, 0xcc, 0, 0, 1]);
+        message.options.push(OPT_ORO, &[0, 23, 0, 103]);
+        message.options.push(OPT_IA_NA, &ia_na(7, Some("fd00::10".parse().unwrap())));
+        message.serialize()
+    }
+
+    #[test]
+    fn round_trip() {
+        let message = Message::parse(&solicit()).unwrap();
+        assert_eq!(message.msg_type, SOLICIT);
+        assert_eq!(message.transaction_id, [1, 2, 3]);
+        assert_eq!(message.client_id(), Some(&[0, 3, 0, 1, 0xaa, 0xbb, 0xcc, 0, 0, 1][..]));
+        assert!(message.requests(OPT_DNS_SERVERS) && message.requests(OPT_CAPTIVE_PORTAL));
+        assert!(!message.requests(OPT_RAPID_COMMIT));
+
+        let ias = message.ia_nas().unwrap();
+        assert_eq!(ias.len(), 1);
+        assert_eq!((ias[0].iaid, ias[0].t1, ias[0].t2), (7, 1800, 2880));
+        assert_eq!(ias[0].addrs[0].addr, "fd00::10".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(message.serialize(), solicit());
+
+        let mut ia = IaNa::new(7);
+        ia.status = Some((STATUS_NO_BINDING, "Unknown binding".to_string()));
+        assert_eq!(IaNa::parse(&ia.encode()).unwrap().status, ia.status);
+    }
+
+    #[test]
+    fn truncated_messages() {
+        assert_eq!(Message::parse(&[SOLICIT, 1, 2]).err(), Some(Dhcp6Error::Truncated(3)));
+        // Room for part of an option header only
+        assert_eq!(Message::parse(&[SOLICIT, 1, 2, 3, 0, 1]).err(), Some(Dhcp6Error::Truncated(2)));
+        assert!(Message::parse(&[SOLICIT, 1, 2, 3]).is_ok());
+    }
+
+    #[test]
+    fn malformed_options() {
+        // Client identifier claiming more bytes than there are
+        assert_eq!(Message::parse(&[SOLICIT, 1, 2, 3, 0, 1, 0, 9, 1, 2]).err(), Some(Dhcp6Error::MalformedOption(OPT_CLIENTID)));
+
+        let with_ia = |ia: &[u8]| {
+            let mut message = Message::parse(&[REQUEST, 1, 2, 3]).unwrap();
+            message.options.push(OPT_IA_NA, ia);
+            Message::parse(&message.serialize()).unwrap()
+        };
+        assert_eq!(with_ia(&[0; 11]).ia_nas().err(), Some(Dhcp6Error::MalformedOption(OPT_IA_NA)));
+
+        // Address option too short for its fixed fields, or running past the IA it sits in
+        let mut ia = ia_na(7, None);
+        ia.extend([0, 5, 0, 4, 0, 0, 0, 0]);
+        assert_eq!(with_ia(&ia).ia_nas().err(), Some(Dhcp6Error::MalformedOption(OPT_IAADDR)));
+        let mut ia = ia_na(7, Some("fd00::10".parse().unwrap()));
+        ia[IA_NA_LEN + 3] += 1;
+        assert_eq!(with_ia(&ia).ia_nas().err(), Some(Dhcp6Error::MalformedOption(OPT_IAADDR)));
+    }
+
+    #[test]
+    fn empty_client_id_is_none() {
+        assert_eq!(Message::parse(&[SOLICIT, 1, 2, 3, 0, 1, 0, 0]).unwrap().client_id(), None);
+    }
+
+    #[test]
+    fn odd_option_request_option() {
+        let message = Message::parse(&[SOLICIT, 1, 2, 3, 0, 6, 0, 3, 0, 23, 0]).unwrap();
+        assert!(message.requests(OPT_DNS_SERVERS));
+        assert!(!message.requests(0));
+    }
+
+    #[test]
+    fn cut_messages_do_not_panic() {
+        let buffer = solicit();
+        for len in 0..buffer.len() {
+            if let Ok(message) = Message::parse(&buffer[..len]) {
+                let _ = message.ia_nas();
+                let _ = message.requests(OPT_DNS_SERVERS);
+            }
+        }
+        // And cut inside the IA, as if its option were that short
+        let ia = ia_na(7, Some("fd00::10".parse().unwrap()));
+        for len in 0..ia.len() {
+            let _ = IaNa::parse(&ia[..len]);
+        }
+    }
+}
@@ -0,0 +1,406 @@
+pub mod lease;
+pub mod message;
+
+use crate::server::{iface, portal};
+use crate::{lock, receiver, server::*, server_state};
+use confee::conf::*;
+use lease::LeaseTable;
+use message::*;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+// Where clients send everything but unicast renewals, RFC 8415 section 7.1
+const ALL_DHCP_RELAY_AGENTS_AND_SERVERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 2);
+// DUID based on the link-layer address, for Ethernet
+const DUID_LL_ETHERNET: [u8; 4] = [0, 3, 0, 1];
+// How often expired leases are looked for
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Stateful DHCPv6 server for clients that do not settle for SLAAC.
+///
+/// Unlike the IPv4 server, leases live in memory only: they are not written
+/// to the lease file, do not show up in the client registry and publish no
+/// lease events, so hooks, the portal and DNS names only follow IPv4 leases.
+pub struct Dhcp6 {
+    iface: String,
+    port: u16,
+    leases: Option<Mutex<LeaseTable>>,
+    dns_servers: Vec<Ipv6Addr>,
+    captive_portal_uri: String,
+    pub state: ServerState,
+}
+
+impl Server for Dhcp6 {
+    fn create(conf: &Conf) -> Self {
+        let link_addr: Ipv4Addr = conf.get("link_addr").unwrap();
+        let mut dhcp6 = Dhcp6 {
+            iface: conf.get("link_iface").unwrap(),
+            port: conf.get("dhcp6_port").unwrap(),
+            leases: None,
+            dns_servers: Self::parse_addr_list(&conf["dhcp6_dns_server"])
+                .unwrap_or_else(|| conf.get::<Ipv6Addr>("link_addr6").into_iter().collect()),
+            captive_portal_uri: portal::captive_portal_uri(conf, link_addr),
+            state: server_state!(),
+        };
+        dhcp6.state.prefix = String::from("dhcp6");
+
+        if !conf["dhcp6_pool_start"].is_empty() || !conf["dhcp6_pool_end"].is_empty() {
+            match (conf.get::<Ipv6Addr>("dhcp6_pool_start"), conf.get::<Ipv6Addr>("dhcp6_pool_end")) {
+                (Some(start), Some(end)) if start <= end => {
+                    dhcp6.leases = Some(Mutex::new(LeaseTable::new(start, end, conf.get("dhcp6_lease_time").unwrap())));
+                }
+                _ => dhcp6.log("Ignoring dhcp6_pool_start and dhcp6_pool_end, they must be two addresses in ascending order"),
+            }
+        }
+        dhcp6
+    }
+
+    fn mainloop(&self) {
+        let socket = match &self.leases {
+            Some(leases) => match self.open_socket() {
+                Ok((socket, ifindex, duid)) => {
+                    lock!(leases, leases => {
+                        self.log(&format!("Serving DHCPv6 on {} port {}, {}s leases", self.iface, self.port, leases.lease_time()));
+                    });
+                    Some((socket, ifindex, duid))
+                }
+                Err(e) => {
+                    self.log(&format!("Could not listen on {}: {}", self.iface, e));
+                    None
+                }
+            },
+            None => {
+                self.log("No dhcp6_pool_start, not serving DHCPv6");
+                None
+            }
+        };
+
+        let mut last_maintenance = Instant::now();
+        loop {
+            lock!(receiver!(self), rx => {
+                if rx.try_recv().is_ok() {
+                    self.log("Stop signal received. Shutting down.");
+                    break;
+                }
+            });
+
+            if let (Some((socket, ifindex, duid)), Some(leases)) = (&socket, &self.leases) {
+                self.receive(socket, *ifindex, duid, leases);
+                if last_maintenance.elapsed() >= MAINTENANCE_INTERVAL {
+                    for lease in lock!(leases, leases => leases.expire()) {
+                        self.log(&format!("Lease of {} for {} expired", lease.addr, Self::ia_label(&lease.duid, lease.iaid)));
+                    }
+                    last_maintenance = Instant::now();
+                }
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        self.log("Stopped");
+    }
+}
+
+impl Dhcp6 {
+    fn parse_addr_list(value: &str) -> Option<Vec<Ipv6Addr>> {
+        let addrs: Vec<Ipv6Addr> = value
+            .split(',')
+            .filter_map(|addr| addr.trim().parse().ok())
+            .collect();
+        if addrs.is_empty() {
+            None
+        } else {
+            Some(addrs)
+        }
+    }
+
+    // Listens on the multicast group of link_iface and names the server after the interface's MAC
+    fn open_socket(&self) -> io::Result<(UdpSocket, u32, Vec<u8>)> {
+        let ifindex = iface::interface_index(&self.iface)? as u32;
+        let mut duid = DUID_LL_ETHERNET.to_vec();
+        duid.extend(iface::interface_mac(&self.iface)?.0);
+
+        let socket = UdpSocket::bind(SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, self.port, 0, 0)))?;
+        socket.join_multicast_v6(&ALL_DHCP_RELAY_AGENTS_AND_SERVERS, ifindex)?;
+        socket.set_nonblocking(true)?;
+        Ok((socket, ifindex, duid))
+    }
+
+    // Names a client for logs by its DUID, e.g. `00:03:00:01:aa:bb:cc:dd:ee:ff`
+    fn label(duid: &[u8]) -> String {
+        let octets: Vec<String> = duid.iter().map(|byte| format!("{:02x}", byte)).collect();
+        octets.join(":")
+    }
+
+    // Names one of a client's IAs for logs, e.g. `00:03:00:01:aa:bb:cc:dd:ee:ff/1`
+    fn ia_label(duid: &[u8], iaid: u32) -> String {
+        format!("{}/{}", Self::label(duid), iaid)
+    }
+
+    fn receive(&self, socket: &UdpSocket, ifindex: u32, duid: &[u8], leases: &Mutex<LeaseTable>) {
+        let mut buffer = [0; 1500];
+        match socket.recv_from(&mut buffer) {
+            Ok((n, SocketAddr::V6(addr))) if addr.scope_id() == ifindex || !addr.ip().is_unicast_link_local() => {
+                match Message::parse(&buffer[..n]) {
+                    Ok(request) if request.msg_type == RELAY_FORW => {
+                        self.log(&format!("Ignoring relayed message from {}, relays are not supported", addr));
+                    }
+                    Ok(request) => {
+                        if let Some(reply) = self.handle_request(&request, duid, leases) {
+                            if let Err(e) = socket.send_to(&reply.serialize(), addr) {
+                                self.log(&format!("Failed to send reply to {}: {}", addr, e));
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        self.log(&format!("Dropping message from {}: {}", addr, e));
+                    }
+                }
+            }
+            Ok(_) => {
+                // Another interface, or an IPv4 sender on a dual-stack socket
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                // No messages available, continue looping
+            }
+            Err(e) => {
+                self.log(&format!("Error receiving request: {}", e));
+            }
+        }
+    }
+
+    fn handle_request(&self, request: &Message, duid: &[u8], leases: &Mutex<LeaseTable>) -> Option<Message> {
+        let Some(client_id) = request.client_id() else {
+            if request.msg_type != INFORMATION_REQUEST {
+                self.log(&format!("Ignoring message type {} without a client identifier", request.msg_type));
+                return None;
+            }
+            return Some(self.create_reply(request, REPLY, duid, Vec::new()));
+        };
+        let ias = match request.ia_nas() {
+            Ok(ias) => ias,
+            Err(e) => {
+                self.log(&format!("Dropping message type {} from {}: {}", request.msg_type, Self::label(client_id), e));
+                return None;
+            }
+        };
+
+        // Everything but Solicit and Rebind names the server it is meant for, RFC 8415 section 16
+        let for_us = request.server_id() == Some(duid);
+        match request.msg_type {
+            SOLICIT if request.server_id().is_none() => {
+                let rapid_commit = request.options.contains(OPT_RAPID_COMMIT);
+                let ias = ias
+                    .iter()
+                    .map(|ia| {
+                        let hint = ia.addrs.first().map(|addr| addr.addr);
+                        if rapid_commit {
+                            self.bound_ia(ia.iaid, lock!(leases, leases => leases.bind(client_id, ia.iaid, hint)), client_id, leases)
+                        } else {
+                            let offered = lock!(leases, leases => leases.offer(client_id, ia.iaid, hint));
+                            match offered {
+                                Some(addr) => {
+                                    self.log(&format!("Offering {} to {}", addr, Self::ia_label(client_id, ia.iaid)));
+                                    self.create_ia(ia.iaid, addr, leases)
+                                }
+                                None => self.unavailable_ia(ia.iaid, client_id),
+                            }
+                        }
+                    })
+                    .collect();
+                let mut reply = self.create_reply(request, if rapid_commit { REPLY } else { ADVERTISE }, duid, ias);
+                if rapid_commit {
+                    reply.options.push(OPT_RAPID_COMMIT, &[]);
+                }
+                Some(reply)
+            }
+            REQUEST if for_us => {
+                let ias = ias
+                    .iter()
+                    .map(|ia| {
+                        let hint = ia.addrs.first().map(|addr| addr.addr);
+                        self.bound_ia(ia.iaid, lock!(leases, leases => leases.bind(client_id, ia.iaid, hint)), client_id, leases)
+                    })
+                    .collect();
+                Some(self.create_reply(request, REPLY, duid, ias))
+            }
+            RENEW if for_us => Some(self.renew(request, client_id, &ias, duid, leases)),
+            REBIND if request.server_id().is_none() => Some(self.renew(request, client_id, &ias, duid, leases)),
+            RELEASE if for_us => {
+                for ia in &ias {
+                    if let Some(lease) = lock!(leases, leases => leases.release(client_id, ia.iaid)) {
+                        self.log(&format!("Lease of {} for {} released", lease.addr, Self::ia_label(client_id, ia.iaid)));
+                    }
+                }
+                let mut reply = self.create_reply(request, REPLY, duid, Vec::new());
+                reply.options.push_status(STATUS_SUCCESS, "Released");
+                Some(reply)
+            }
+            INFORMATION_REQUEST if request.server_id().is_none() || for_us => Some(self.create_reply(request, REPLY, duid, Vec::new())),
+            SOLICIT | REQUEST | RENEW | REBIND | RELEASE | INFORMATION_REQUEST => {
+                self.log(&format!("Ignoring message type {} from {} meant for another server", request.msg_type, Self::label(client_id)));
+                None
+            }
+            msg_type => {
+                self.log(&format!("Ignoring unsupported message type {} from {}", msg_type, Self::label(client_id)));
+                None
+            }
+        }
+    }
+
+    // Addresses the client asks to renew that are not the one it holds get zero lifetimes, RFC 8415 section 18.3.4
+    fn renew(&self, request: &Message, client_id: &[u8], ias: &[IaNa], duid: &[u8], leases: &Mutex<LeaseTable>) -> Message {
+        let ias = ias
+            .iter()
+            .map(|ia| match lock!(leases, leases => leases.renew(client_id, ia.iaid)) {
+                Some(lease) => {
+                    self.log(&format!("Lease of {} for {} renewed", lease.addr, Self::ia_label(client_id, ia.iaid)));
+                    let mut reply_ia = self.create_ia(ia.iaid, lease.addr, leases);
+                    for stale in ia.addrs.iter().filter(|addr| addr.addr != lease.addr) {
+                        self.log(&format!("Withdrawing {} from {}, it holds {}", stale.addr, Self::ia_label(client_id, ia.iaid), lease.addr));
+                        reply_ia.addrs.push(message::IaAddr {
+                            addr: stale.addr,
+                            preferred_lifetime: 0,
+                            valid_lifetime: 0,
+                        });
+                    }
+                    reply_ia
+                }
+                None => {
+                    let mut reply_ia = IaNa::new(ia.iaid);
+                    reply_ia.status = Some((STATUS_NO_BINDING, "Unknown binding".to_string()));
+                    reply_ia
+                }
+            })
+            .collect();
+        self.create_reply(request, REPLY, duid, ias)
+    }
+
+    fn bound_ia(&self, iaid: u32, lease: Option<lease::Lease>, client_id: &[u8], leases: &Mutex<LeaseTable>) -> IaNa {
+        match lease {
+            Some(lease) => {
+                self.log(&format!("Lease of {} for {} granted", lease.addr, Self::ia_label(client_id, iaid)));
+                self.create_ia(iaid, lease.addr, leases)
+            }
+            None => self.unavailable_ia(iaid, client_id),
+        }
+    }
+
+    fn unavailable_ia(&self, iaid: u32, client_id: &[u8]) -> IaNa {
+        self.log(&format!("No address left for {}", Self::ia_label(client_id, iaid)));
+        let mut ia = IaNa::new(iaid);
+        ia.status = Some((STATUS_NO_ADDRS_AVAIL, "Pool exhausted".to_string()));
+        ia
+    }
+
+    fn create_ia(&self, iaid: u32, addr: Ipv6Addr, leases: &Mutex<LeaseTable>) -> IaNa {
+        let lease_time = lock!(leases, leases => leases.lease_time());
+        let mut ia = IaNa::new(iaid);
+        ia.t1 = lease_time / 2;
+        ia.t2 = lease_time - lease_time / 5;
+        ia.addrs.push(message::IaAddr {
+            addr,
+            preferred_lifetime: lease_time,
+            valid_lifetime: lease_time,
+        });
+        ia
+    }
+
+    fn create_reply(&self, request: &Message, msg_type: u8, duid: &[u8], ias: Vec<IaNa>) -> Message {
+        let mut reply = request.reply(msg_type);
+        reply.options.push(OPT_SERVERID, duid);
+        if let Some(client_id) = request.client_id() {
+            reply.options.push(OPT_CLIENTID, client_id);
+        }
+        for ia in ias {
+            reply.options.push(OPT_IA_NA, &ia.encode());
+        }
+
+        // Configuration options only go to clients that ask for them
+        if request.requests(OPT_DNS_SERVERS) && !self.dns_servers.is_empty() {
+            let servers: Vec<u8> = self.dns_servers.iter().flat_map(|addr| addr.octets()).collect();
+            reply.options.push(OPT_DNS_SERVERS, &servers);
+        }
+        if request.requests(OPT_CAPTIVE_PORTAL) && !self.captive_portal_uri.is_empty() {
+            reply.options.push(OPT_CAPTIVE_PORTAL, self.captive_portal_uri.as_bytes());
+        }
+        reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER_DUID: &[u8] = &[0, 3, 0, 1, 2, 0, 0, 0, 0, 1];
+    const CLIENT_DUID: &[u8] = &[0, 3, 0, 1, 0xaa, 0xbb, 0xcc, 0, 0, 1];
+
+    fn server() -> Dhcp6 {
+        Dhcp6 {
+            iface: "test0".to_string(),
+            port: 547,
+            leases: None,
+            dns_servers: vec!["fd00::1".parse().unwrap()],
+            captive_portal_uri: String::new(),
+            state: server_state!(),
+        }
+    }
+
+    fn request(msg_type: u8, server_id: Option<&[u8]>, iaid: u32, addr: Option<Ipv6Addr>) -> Message {
+        let mut message = Message::parse(&[msg_type, 1, 2, 3]).unwrap();
+        message.options.push(OPT_CLIENTID, CLIENT_DUID);
+        if let Some(server_id) = server_id {
+            message.options.push(OPT_SERVERID, server_id);
+        }
+        let mut ia = IaNa::new(iaid);
+        ia.addrs.extend(addr.map(|addr| message::IaAddr {
+            addr,
+            preferred_lifetime: 0,
+            valid_lifetime: 0,
+        }));
+        message.options.push(OPT_IA_NA, &ia.encode());
+        message
+    }
+
+    fn only_ia(reply: &Message) -> IaNa {
+        let mut ias = reply.ia_nas().unwrap();
+        assert_eq!(ias.len(), 1);
+        ias.remove(0)
+    }
+
+    #[test]
+    fn solicit_advertise_request_renew_release() {
+        let server = server();
+        let leases = Mutex::new(LeaseTable::new("fd00::10".parse().unwrap(), "fd00::1f".parse().unwrap(), 3600));
+        let handle = |request: Message| server.handle_request(&request, SERVER_DUID, &leases).unwrap();
+
+        let advertise = handle(request(SOLICIT, None, 1, None));
+        assert_eq!(advertise.msg_type, ADVERTISE);
+        assert_eq!(advertise.server_id(), Some(SERVER_DUID));
+        assert_eq!(advertise.client_id(), Some(CLIENT_DUID));
+        let addr = only_ia(&advertise).addrs[0].addr;
+
+        let reply = handle(request(REQUEST, Some(SERVER_DUID), 1, Some(addr)));
+        assert_eq!(reply.msg_type, REPLY);
+        let ia = only_ia(&reply);
+        assert_eq!((ia.addrs[0].addr, ia.addrs[0].valid_lifetime, ia.t1), (addr, 3600, 1800));
+
+        // Requests for another server go unanswered
+        assert!(server.handle_request(&request(REQUEST, Some(CLIENT_DUID), 1, None), SERVER_DUID, &leases).is_none());
+
+        let ia = only_ia(&handle(request(RENEW, Some(SERVER_DUID), 1, Some(addr))));
+        assert_eq!(ia.addrs[0].addr, addr);
+        assert_eq!(ia.status, None);
+
+        let ia = only_ia(&handle(request(RENEW, Some(SERVER_DUID), 2, None)));
+        assert!(ia.addrs.is_empty());
+        assert_eq!(ia.status.map(|(status, _)| status), Some(STATUS_NO_BINDING));
+
+        let reply = handle(request(RELEASE, Some(SERVER_DUID), 1, Some(addr)));
+        assert_eq!(reply.options.get(OPT_STATUS_CODE).map(|status| &status[..2]), Some(&STATUS_SUCCESS.to_be_bytes()[..]));
+        let ia = only_ia(&handle(request(RENEW, Some(SERVER_DUID), 1, Some(addr))));
+        assert_eq!(ia.status.map(|(status, _)| status), Some(STATUS_NO_BINDING));
+    }
+}
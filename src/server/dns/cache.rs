@@ -0,0 +1,299 @@
+use crate::server::dns::message::*;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+// What a cached answer is for: name, type and class of the question
+type Key = (Name, u16, u16);
+
+struct Entry {
+    rcode: u8,
+    answers: Vec<Record>,
+    authority: Vec<Record>,
+    additional: Vec<Record>,
+    stored: Instant,
+    ttl: u32,
+    // Position in the recency order, higher is more recent
+    used: u64,
+}
+
+/// Answers of the upstream resolvers, kept for as long as their TTLs allow.
+///
+/// TTLs are clamped to `min_ttl..=max_ttl`. NXDOMAIN and NODATA answers are
+/// kept as long as their SOA says, RFC 2308 section 5, and not at all without
+/// one. Once `capacity` answers are kept, the least recently used one goes.
+pub struct Cache {
+    capacity: usize,
+    min_ttl: u32,
+    max_ttl: u32,
+    entries: HashMap<Key, Entry>,
+    recency: BTreeMap<u64, Key>,
+    clock: u64,
+}
+
+impl Cache {
+    pub fn new(capacity: usize, min_ttl: u32, max_ttl: u32) -> Self {
+        Cache {
+            capacity,
+            min_ttl,
+            max_ttl: max_ttl.max(min_ttl),
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn key(message: &Message) -> Option<Key> {
+        match message.questions.as_slice() {
+            [question] if message.header.opcode == OPCODE_QUERY => Some((question.name.clone(), question.qtype, question.qclass)),
+            _ => None,
+        }
+    }
+
+    /// Answers the query from the cache, with TTLs counting down since the answer came in
+    pub fn get(&mut self, query: &Message) -> Option<Message> {
+        let key = Self::key(query)?;
+        let entry = self.entries.get_mut(&key)?;
+        let elapsed = u32::try_from(entry.stored.elapsed().as_secs()).unwrap_or(u32::MAX);
+        if elapsed >= entry.ttl {
+            self.remove(&key);
+            return None;
+        }
+
+        self.clock += 1;
+        self.recency.remove(&entry.used);
+        self.recency.insert(self.clock, key);
+        entry.used = self.clock;
+
+        let age = |records: &[Record]| -> Vec<Record> {
+            records
+                .iter()
+                .map(|record| Record {
+                    ttl: record.ttl - elapsed,
+                    ..record.clone()
+                })
+                .collect()
+        };
+        let mut response = query.response();
+        response.header.rcode = entry.rcode;
+        response.header.recursion_available = true;
+        response.answers = age(&entry.answers);
+        response.authority = age(&entry.authority);
+        response.additional = age(&entry.additional);
+        Some(response)
+    }
+
+    /// Keeps an upstream response, if it is an answer worth keeping
+    pub fn insert(&mut self, response: &Message) {
+        if self.capacity == 0 || response.header.truncated {
+            return;
+        }
+        let Some(key) = Self::key(response) else {
+            return;
+        };
+        let ttl = match response.header.rcode {
+            0 if !response.answers.is_empty() => response.answers.iter().map(|record| record.ttl).min(),
+            0 | RCODE_NXDOMAIN => Self::negative_ttl(response),
+            _ => None,
+        };
+        let Some(ttl) = ttl.map(|ttl| ttl.clamp(self.min_ttl, self.max_ttl)).filter(|ttl| *ttl > 0) else {
+            return;
+        };
+
+        self.clock += 1;
+        // No record may outlive the entry. EDNS options belong to the client that asked.
+        let clamp = |records: &[Record]| -> Vec<Record> {
+            records
+                .iter()
+                .filter(|record| record.rtype != TYPE_OPT)
+                .map(|record| Record {
+                    ttl: record.ttl.clamp(ttl, self.max_ttl),
+                    ..record.clone()
+                })
+                .collect()
+        };
+        let entry = Entry {
+            rcode: response.header.rcode,
+            answers: clamp(&response.answers),
+            authority: clamp(&response.authority),
+            additional: clamp(&response.additional),
+            stored: Instant::now(),
+            ttl,
+            used: self.clock,
+        };
+
+        self.remove(&key);
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        self.recency.insert(entry.used, key.clone());
+        self.entries.insert(key, entry);
+    }
+
+    // The lower of the SOA's own TTL and its minimum field, RFC 2308 section 5
+    fn negative_ttl(response: &Message) -> Option<u32> {
+        response.authority.iter().find_map(|record| match &record.data {
+            RData::Soa(soa) => Some(record.ttl.min(soa.minimum)),
+            _ => None,
+        })
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.used);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    fn query(owner: &str) -> Message {
+        let mut query = Message::default();
+        query.header.id = 7;
+        query.questions.push(Question {
+            name: owner.parse().unwrap(),
+            qtype: TYPE_A,
+            qclass: CLASS_IN,
+        });
+        query
+    }
+
+    fn answer(owner: &str, ttl: u32) -> Message {
+        let mut response = query(owner).response();
+        response.answers.push(Record {
+            name: owner.parse().unwrap(),
+            rtype: TYPE_A,
+            class: CLASS_IN,
+            ttl,
+            data: RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+        });
+        response
+    }
+
+    fn nxdomain(owner: &str, ttl: u32, minimum: u32) -> Message {
+        let mut response = query(owner).response();
+        response.header.rcode = RCODE_NXDOMAIN;
+        response.authority.push(Record {
+            name: "example.com".parse().unwrap(),
+            rtype: TYPE_SOA,
+            class: CLASS_IN,
+            ttl,
+            data: RData::Soa(Soa {
+                mname: "ns.example.com".parse().unwrap(),
+                rname: "hostmaster.example.com".parse().unwrap(),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum,
+            }),
+        });
+        response
+    }
+
+    // Makes the entry for `owner` look `secs` seconds older
+    fn age(cache: &mut Cache, owner: &str, secs: u64) {
+        let key = Cache::key(&query(owner)).unwrap();
+        let entry = cache.entries.get_mut(&key).unwrap();
+        entry.stored -= Duration::from_secs(secs);
+    }
+
+    #[test]
+    fn ttls_are_clamped() {
+        let mut cache = Cache::new(10, 60, 3600);
+        cache.insert(&answer("short.example.com", 5));
+        cache.insert(&answer("long.example.com", 86400));
+        assert_eq!(cache.get(&query("short.example.com")).unwrap().answers[0].ttl, 60);
+        assert_eq!(cache.get(&query("long.example.com")).unwrap().answers[0].ttl, 3600);
+    }
+
+    #[test]
+    fn zero_ttls_are_not_kept() {
+        let mut cache = Cache::new(10, 0, 3600);
+        cache.insert(&answer("www.example.com", 0));
+        assert!(cache.get(&query("www.example.com")).is_none());
+    }
+
+    #[test]
+    fn ttls_count_down() {
+        let mut cache = Cache::new(10, 0, 3600);
+        cache.insert(&answer("www.example.com", 300));
+        age(&mut cache, "www.example.com", 100);
+
+        let response = cache.get(&query("www.example.com")).unwrap();
+        assert_eq!(response.header.id, 7);
+        assert_eq!(response.answers[0].ttl, 200);
+
+        age(&mut cache, "www.example.com", 200);
+        assert!(cache.get(&query("www.example.com")).is_none());
+        assert!(cache.entries.is_empty() && cache.recency.is_empty());
+    }
+
+    #[test]
+    fn negative_ttl_comes_from_the_soa() {
+        let mut cache = Cache::new(10, 0, 3600);
+        cache.insert(&nxdomain("a.example.com", 900, 120));
+        cache.insert(&nxdomain("b.example.com", 30, 120));
+
+        let response = cache.get(&query("a.example.com")).unwrap();
+        assert_eq!(response.header.rcode, RCODE_NXDOMAIN);
+        assert_eq!(response.authority.len(), 1);
+        age(&mut cache, "a.example.com", 120);
+        assert!(cache.get(&query("a.example.com")).is_none());
+
+        age(&mut cache, "b.example.com", 29);
+        assert!(cache.get(&query("b.example.com")).is_some());
+        age(&mut cache, "b.example.com", 1);
+        assert!(cache.get(&query("b.example.com")).is_none());
+    }
+
+    #[test]
+    fn negative_answers_without_soa_are_not_kept() {
+        let mut cache = Cache::new(10, 60, 3600);
+        let mut response = query("www.example.com").response();
+        response.header.rcode = RCODE_NXDOMAIN;
+        cache.insert(&response);
+        // NODATA without an SOA either
+        cache.insert(&query("other.example.com").response());
+
+        let mut failure = answer("failed.example.com", 300);
+        failure.header.rcode = RCODE_SERVFAIL;
+        cache.insert(&failure);
+        let mut truncated = answer("big.example.com", 300);
+        truncated.header.truncated = true;
+        cache.insert(&truncated);
+
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn least_recently_used_goes_first() {
+        let mut cache = Cache::new(2, 0, 3600);
+        cache.insert(&answer("a.example.com", 300));
+        cache.insert(&answer("b.example.com", 300));
+        assert!(cache.get(&query("a.example.com")).is_some());
+        cache.insert(&answer("c.example.com", 300));
+
+        assert!(cache.get(&query("b.example.com")).is_none());
+        assert!(cache.get(&query("a.example.com")).is_some());
+        assert!(cache.get(&query("c.example.com")).is_some());
+        assert_eq!(cache.recency.len(), 2);
+    }
+
+    #[test]
+    fn replacing_an_answer_evicts_nothing() {
+        let mut cache = Cache::new(2, 0, 3600);
+        cache.insert(&answer("a.example.com", 300));
+        cache.insert(&answer("b.example.com", 300));
+        cache.insert(&answer("a.example.com", 600));
+
+        assert_eq!(cache.get(&query("a.example.com")).unwrap().answers[0].ttl, 600);
+        assert!(cache.get(&query("b.example.com")).is_some());
+    }
+}
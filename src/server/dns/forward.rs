@@ -0,0 +1,286 @@
+use crate::server::dns::message::{Message, RCODE_REFUSED, RCODE_SERVFAIL};
+use crate::server::dns::tcp;
+use rand::Rng;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DNS_PORT: u16 = 53;
+// Queries waiting for an upstream answer at once, beyond that clients get SERVFAIL right away.
+// Each holds a socket of its own, this keeps them well under the usual limit of 1024 files.
+const MAX_PENDING: usize = 512;
+
+/// Parses an upstream resolver, written as an address with an optional port
+pub fn parse_upstream(s: &str) -> Result<SocketAddr, String> {
+    s.parse::<SocketAddr>()
+        .or_else(|_| s.parse::<IpAddr>().map(|addr| SocketAddr::new(addr, DNS_PORT)))
+        .map_err(|_| "not an address with an optional port".to_string())
+}
+
+struct Pending<T> {
+    origin: T,
+    // As the client sent it, with its own ID
+    query: Message,
+    upstream: usize,
+    tried: usize,
+    deadline: Instant,
+    // Why sending to the current upstream failed, if it did
+    error: Option<String>,
+    // Largest answer the client takes, truncated ones are fetched again over TCP if it takes more than UDP gives
+    max_size: usize,
+    // Waiting for the current upstream to answer over TCP
+    over_tcp: bool,
+    // Connected to the current upstream from a fresh port, so answers cannot be spoofed by guessing the ID alone
+    socket: Option<UdpSocket>,
+}
+
+// A socket on a random port of its own for one attempt at a query
+fn query_socket(upstream: SocketAddr) -> io::Result<UdpSocket> {
+    let unspecified = if upstream.is_ipv4() { IpAddr::V4(Ipv4Addr::UNSPECIFIED) } else { IpAddr::V6(Ipv6Addr::UNSPECIFIED) };
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+    socket.connect(upstream)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+// Asks the upstream over TCP, blocking for up to a timeout on each step
+fn query_tcp(upstream: SocketAddr, query: &[u8], timeout: Duration) -> Result<Message, String> {
+    let mut stream = TcpStream::connect_timeout(&upstream, timeout).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    stream.set_write_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    stream.write_all(&tcp::frame(query)).map_err(|e| e.to_string())?;
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).map_err(|e| e.to_string())?;
+    let mut response = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut response).map_err(|e| e.to_string())?;
+    Message::parse(&response).map_err(|e| e.to_string())
+}
+
+/// Relays queries to upstream resolvers without blocking.
+///
+/// Every attempt at a query is sent from a socket of its own, on a port the
+/// kernel picks at random. Queries go to the upstream that answered last. One that does not answer
+/// within the timeout, or answers SERVFAIL or REFUSED, is skipped for the
+/// next one until all have been tried. `T` tells where the answer goes back to.
+///
+/// Answers the upstream truncated are asked for again over TCP, on a thread
+/// of their own, when the client can take them whole.
+pub struct Forwarder<T> {
+    upstreams: Vec<SocketAddr>,
+    timeout: Duration,
+    pending: HashMap<u16, Pending<T>>,
+    preferred: usize,
+    tcp_sender: Sender<(u16, SocketAddr, Result<Message, String>)>,
+    tcp_answers: Receiver<(u16, SocketAddr, Result<Message, String>)>,
+}
+
+impl<T> Forwarder<T> {
+    pub fn new(upstreams: Vec<SocketAddr>, timeout: Duration) -> io::Result<Self> {
+        let (tcp_sender, tcp_answers) = mpsc::channel();
+        Ok(Forwarder {
+            upstreams,
+            timeout,
+            pending: HashMap::new(),
+            preferred: 0,
+            tcp_sender,
+            tcp_answers,
+        })
+    }
+
+    /// Sends the query upstream, its answer comes out of a later `poll`.
+    ///
+    /// `max_size` is the largest answer the client takes, truncated answers are fetched whole up to it.
+    pub fn forward(&mut self, query: Message, origin: T, max_size: usize) -> Result<(), String> {
+        if self.pending.len() >= MAX_PENDING {
+            return Err(format!("{} queries already waiting upstream", MAX_PENDING));
+        }
+        let id = loop {
+            let id = rand::thread_rng().gen::<u16>();
+            if !self.pending.contains_key(&id) {
+                break id;
+            }
+        };
+        let mut pending = Pending {
+            origin,
+            query,
+            upstream: self.preferred,
+            tried: 1,
+            deadline: Instant::now(),
+            error: None,
+            max_size,
+            over_tcp: false,
+            socket: None,
+        };
+        self.send(id, &mut pending);
+        self.pending.insert(id, pending);
+        Ok(())
+    }
+
+    // An upstream that cannot even be sent to is given up on at the next poll
+    fn send(&self, id: u16, pending: &mut Pending<T>) {
+        let upstream = self.upstreams[pending.upstream];
+        let mut query = pending.query.clone();
+        query.header.id = id;
+        pending.over_tcp = false;
+        // Dropping the last attempt's socket closes its port to late answers
+        pending.socket = None;
+        let sent = query_socket(upstream).and_then(|socket| {
+            socket.send(&query.serialize())?;
+            Ok(socket)
+        });
+        match sent {
+            Ok(socket) => {
+                pending.socket = Some(socket);
+                pending.deadline = Instant::now() + self.timeout;
+            }
+            Err(e) => {
+                pending.deadline = Instant::now();
+                pending.error = Some(e.to_string());
+            }
+        }
+    }
+
+    // Asks the current upstream again over TCP, allowing for both connecting and reading
+    fn send_tcp(&self, id: u16, pending: &mut Pending<T>) {
+        let upstream = self.upstreams[pending.upstream];
+        let mut query = pending.query.clone();
+        query.header.id = id;
+        let timeout = self.timeout;
+        let sender = self.tcp_sender.clone();
+        thread::spawn(move || {
+            let _ = sender.send((id, upstream, query_tcp(upstream, &query.serialize(), timeout)));
+        });
+        pending.over_tcp = true;
+        pending.socket = None;
+        pending.deadline = Instant::now() + 2 * timeout;
+    }
+
+    /// How many answers are still due for the origins `matches` picks
+    pub fn waiting_for(&self, matches: impl Fn(&T) -> bool) -> usize {
+        self.pending.values().filter(|pending| matches(&pending.origin)).count()
+    }
+
+    /// Collects the answers that came in, and gives up on queries no upstream answered.
+    ///
+    /// Each answer comes with the query it answers, as the client sent it. The answer may
+    /// still be larger than the client takes.
+    pub fn poll(&mut self, log: impl Fn(&str)) -> Vec<(T, Message, Message)> {
+        let mut answered = Vec::new();
+        let mut buffer = [0u8; 65535];
+        for (id, pending) in self.pending.iter_mut() {
+            let Some(socket) = &pending.socket else {
+                continue;
+            };
+            let upstream = self.upstreams[pending.upstream];
+            loop {
+                // Connected, so only the upstream's datagrams come in
+                let n = match socket.recv(&mut buffer) {
+                    Ok(n) => n,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        pending.deadline = Instant::now();
+                        pending.error = Some(e.to_string());
+                        break;
+                    }
+                };
+                let response = match Message::parse(&buffer[..n]) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        log(&format!("Malformed response from {}: {}", upstream, e));
+                        continue;
+                    }
+                };
+                // Anything not matching the query we sent is spoofed
+                if response.header.id == *id && response.header.response && pending.query.questions == response.questions {
+                    answered.push((*id, response));
+                    break;
+                }
+            }
+        }
+
+        for (id, src, result) in self.tcp_answers.try_iter() {
+            let Some(pending) = self.pending.get_mut(&id).filter(|pending| pending.over_tcp && self.upstreams[pending.upstream] == src) else {
+                continue;
+            };
+            match result {
+                Ok(response) if response.header.response && pending.query.questions == response.questions => answered.push((id, response)),
+                Ok(_) => {
+                    pending.deadline = Instant::now();
+                    pending.error = Some("mismatched answer over TCP".to_string());
+                }
+                Err(e) => {
+                    pending.deadline = Instant::now();
+                    pending.error = Some(format!("over TCP: {}", e));
+                }
+            }
+        }
+
+        let mut done = Vec::new();
+        for (id, mut response) in answered {
+            let pending = self.pending.get_mut(&id).expect("answered query is pending");
+            if response.header.truncated && pending.max_size > pending.query.max_udp_size() && !pending.over_tcp {
+                let mut pending = self.pending.remove(&id).expect("answered query is pending");
+                self.send_tcp(id, &mut pending);
+                self.pending.insert(id, pending);
+                continue;
+            }
+            let failed = response.header.rcode == RCODE_SERVFAIL || response.header.rcode == RCODE_REFUSED;
+            if failed && self.retry(id, &format!("answered with rcode {}", response.header.rcode), &log) {
+                continue;
+            }
+            let pending = self.pending.remove(&id).expect("answered query is pending");
+            self.preferred = pending.upstream;
+            response.header.id = pending.query.header.id;
+            done.push((pending.origin, pending.query, response));
+        }
+
+        let now = Instant::now();
+        let timed_out: Vec<(u16, String)> = self
+            .pending
+            .iter_mut()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(id, pending)| match pending.error.take() {
+                Some(e) => (*id, format!("is unreachable ({})", e)),
+                None => (*id, "timed out".to_string()),
+            })
+            .collect();
+        for (id, reason) in timed_out {
+            if self.retry(id, &reason, &log) {
+                continue;
+            }
+            let pending = self.pending.remove(&id).expect("timed out query is pending");
+            log("No upstream answered, sending SERVFAIL");
+            let mut response = pending.query.response();
+            response.header.rcode = RCODE_SERVFAIL;
+            response.header.recursion_available = true;
+            done.push((pending.origin, pending.query, response));
+        }
+        done
+    }
+
+    // Moves a query on to the next upstream, false once every upstream had its chance
+    fn retry(&mut self, id: u16, reason: &str, log: &impl Fn(&str)) -> bool {
+        let count = self.upstreams.len();
+        let Some(pending) = self.pending.get_mut(&id) else {
+            return false;
+        };
+        let failed = pending.upstream;
+        if failed == self.preferred {
+            self.preferred = (failed + 1) % count;
+        }
+        if pending.tried >= count {
+            return false;
+        }
+        pending.upstream = (failed + 1) % count;
+        pending.tried += 1;
+        log(&format!("Upstream {} {}, trying {}", self.upstreams[failed], reason, self.upstreams[pending.upstream]));
+
+        let mut pending = self.pending.remove(&id).expect("retried query is pending");
+        self.send(id, &mut pending);
+        self.pending.insert(id, pending);
+        true
+    }
+}
@@ -0,0 +1,143 @@
+use crate::server::dns::message::Name;
+use crate::server::reload::WatchedFile;
+use std::str::FromStr;
+
+/// A domain suffix such as `example.com`, matching the domain and every name below it.
+///
+/// A `*` in a label stands for any run of characters, so `*.example.com`
+/// matches the names below `example.com` but not the domain itself, and
+/// `cdn*.example.net` matches `cdn1.example.net` and `img.cdn-eu.example.net`.
+pub struct DomainPattern {
+    labels: Vec<String>,
+}
+
+impl DomainPattern {
+    pub fn matches(&self, name: &Name) -> bool {
+        let labels = name.labels();
+        labels.len() >= self.labels.len()
+            && labels[labels.len() - self.labels.len()..]
+                .iter()
+                .zip(&self.labels)
+                .all(|(label, pattern)| Self::label_matches(&label.to_ascii_lowercase(), pattern.as_bytes()))
+    }
+
+    // Glob match of a single label, `*` being the only special character
+    fn label_matches(label: &[u8], pattern: &[u8]) -> bool {
+        match pattern.split_first() {
+            None => label.is_empty(),
+            Some((b'*', rest)) => (0..=label.len()).any(|skip| Self::label_matches(&label[skip..], rest)),
+            Some((byte, rest)) => label.first() == Some(byte) && Self::label_matches(&label[1..], rest),
+        }
+    }
+}
+
+impl FromStr for DomainPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid domain pattern: {}", s);
+        let s = s.strip_suffix('.').unwrap_or(s);
+        let labels: Vec<String> = s.split('.').map(str::to_ascii_lowercase).collect();
+        if labels.iter().any(|label| label.is_empty() || label.len() > 63) {
+            return Err(invalid());
+        }
+        // A lone `*` would let everything through
+        if labels.iter().all(|label| label.chars().all(|c| c == '*')) {
+            return Err(invalid());
+        }
+        Ok(DomainPattern { labels })
+    }
+}
+
+/// Domains anyone may resolve, before passing the portal too.
+///
+/// Patterns are read from a file, one per line. Empty lines and everything
+/// after a `#` are ignored.
+pub struct WalledGarden {
+    file: Option<WatchedFile>,
+    patterns: Vec<DomainPattern>,
+}
+
+impl WalledGarden {
+    /// Creates the garden from its file, an empty path meaning there is none
+    pub fn new(path: &str) -> Self {
+        WalledGarden {
+            file: Some(path).filter(|path| !path.is_empty()).map(WatchedFile::new),
+            patterns: Vec::new(),
+        }
+    }
+
+    pub fn contains(&self, name: &Name) -> bool {
+        self.patterns.iter().any(|pattern| pattern.matches(name))
+    }
+
+    /// Parses the file again if it changed, returning what happened for the log
+    pub fn reload(&mut self) -> Vec<String> {
+        let Some(file) = &mut self.file else {
+            return Vec::new();
+        };
+        let contents = match file.poll() {
+            None => return Vec::new(),
+            Some(Ok(contents)) => contents,
+            Some(Err(e)) => {
+                return vec![format!("Could not read walled garden {}, keeping {} entries: {}", file.path(), self.patterns.len(), e)];
+            }
+        };
+
+        let mut messages = Vec::new();
+        let mut patterns = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            match line.parse::<DomainPattern>() {
+                Ok(pattern) => patterns.push(pattern),
+                Err(e) => messages.push(format!("Skipping line {} of {}: {}", i + 1, file.path(), e)),
+            }
+        }
+        self.patterns = patterns;
+        messages.push(format!("Loaded {} entries from walled garden {}", self.patterns.len(), file.path()));
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        pattern.parse::<DomainPattern>().unwrap().matches(&name.parse().unwrap())
+    }
+
+    #[test]
+    fn suffixes() {
+        assert!(matches("example.com", "example.com"));
+        assert!(matches("example.com", "www.example.com"));
+        assert!(matches("example.com.", "a.b.Example.COM"));
+        assert!(!matches("example.com", "badexample.com"));
+        assert!(!matches("example.com", "example.com.evil.net"));
+        assert!(!matches("www.example.com", "example.com"));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*.example.com", "www.example.com"));
+        assert!(matches("*.example.com", "a.b.example.com"));
+        assert!(!matches("*.example.com", "example.com"));
+        assert!(!matches("*.example.com", "badexample.com"));
+
+        assert!(matches("cdn*.example.net", "cdn1.example.net"));
+        assert!(matches("cdn*.example.net", "img.cdn-eu.example.net"));
+        assert!(matches("cdn*.example.net", "CDN.example.net"));
+        assert!(!matches("cdn*.example.net", "mycdn.example.net"));
+        assert!(matches("*cdn*.example.net", "mycdn2.example.net"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        for pattern in ["", "*", "*.*", ".example.com", "example..com", &format!("{}.com", "a".repeat(64))] {
+            assert!(pattern.parse::<DomainPattern>().is_err(), "{} parsed", pattern);
+        }
+    }
+}
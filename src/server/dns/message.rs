@@ -0,0 +1,819 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_MX: u16 = 15;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_OPT: u16 = 41;
+
+pub const CLASS_IN: u16 = 1;
+
+pub const OPCODE_QUERY: u8 = 0;
+
+pub const RCODE_FORMERR: u8 = 1;
+pub const RCODE_SERVFAIL: u8 = 2;
+pub const RCODE_NXDOMAIN: u8 = 3;
+pub const RCODE_NOTIMP: u8 = 4;
+pub const RCODE_REFUSED: u8 = 5;
+
+// Mnemonics of the types that can be written in the config
+const TYPE_NAMES: [(&str, u16); 8] = [
+    ("A", TYPE_A),
+    ("NS", TYPE_NS),
+    ("CNAME", TYPE_CNAME),
+    ("SOA", TYPE_SOA),
+    ("PTR", TYPE_PTR),
+    ("MX", TYPE_MX),
+    ("TXT", TYPE_TXT),
+    ("AAAA", TYPE_AAAA),
+];
+
+const HEADER_LEN: usize = 12;
+// Largest message over UDP without EDNS, RFC 1035 section 4.2.1
+const UDP_SIZE: usize = 512;
+// The DNSSEC OK bit in the TTL of an OPT record, RFC 3225 section 3
+const EDNS_DO: u32 = 0x8000;
+const MAX_LABEL_LEN: usize = 63;
+// Counting the length bytes and the root label, RFC 1035 section 2.3.4
+const MAX_NAME_LEN: usize = 255;
+// Compression pointers have 14 bits for the offset
+const MAX_POINTER: usize = 0x3fff;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DnsError {
+    /// Message ends in the middle of the header or of a section
+    Truncated(usize),
+    /// Compression pointer that does not point back to an earlier name
+    BadPointer(usize),
+    /// Label length byte with the reserved 01 or 10 prefix
+    BadLabelType(u8),
+    LabelTooLong,
+    NameTooLong,
+    /// Record whose data does not match its type or its length
+    MalformedRecord(u16),
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DnsError::Truncated(len) => write!(f, "truncated message of {} bytes", len),
+            DnsError::BadPointer(offset) => write!(f, "bad compression pointer to offset {}", offset),
+            DnsError::BadLabelType(byte) => write!(f, "bad label type {:#04x}", byte),
+            DnsError::LabelTooLong => write!(f, "label longer than {} bytes", MAX_LABEL_LEN),
+            DnsError::NameTooLong => write!(f, "name longer than {} bytes", MAX_NAME_LEN),
+            DnsError::MalformedRecord(rtype) => write!(f, "malformed record of type {}", rtype),
+        }
+    }
+}
+
+impl std::error::Error for DnsError {}
+
+/// A domain name. Names compare and hash case-insensitively, as DNS does.
+#[derive(Clone, Debug, Default)]
+pub struct Name {
+    labels: Vec<Vec<u8>>,
+}
+
+impl Name {
+    pub fn root() -> Self {
+        Name { labels: Vec::new() }
+    }
+
+    pub fn labels(&self) -> &[Vec<u8>] {
+        &self.labels
+    }
+
+    /// The name PTR records for `addr` live under, in `in-addr.arpa` or `ip6.arpa`
+    pub fn reverse(addr: IpAddr) -> Self {
+        let mut labels: Vec<Vec<u8>> = match addr {
+            IpAddr::V4(addr) => addr.octets().iter().rev().map(|octet| octet.to_string().into_bytes()).collect(),
+            IpAddr::V6(addr) => addr
+                .octets()
+                .iter()
+                .rev()
+                .flat_map(|octet| [octet & 0x0f, octet >> 4])
+                .map(|nibble| format!("{:x}", nibble).into_bytes())
+                .collect(),
+        };
+        let suffix: &[&str] = if addr.is_ipv4() { &["in-addr", "arpa"] } else { &["ip6", "arpa"] };
+        labels.extend(suffix.iter().map(|label| label.as_bytes().to_vec()));
+        Name { labels }
+    }
+
+    /// Whether this name is `other` or lies below it
+    pub fn is_subdomain_of(&self, other: &Name) -> bool {
+        self.labels.len() >= other.labels.len()
+            && self.labels[self.labels.len() - other.labels.len()..]
+                .iter()
+                .zip(&other.labels)
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    fn suffix(&self, skip: usize) -> Name {
+        Name {
+            labels: self.labels[skip..].to_vec(),
+        }
+    }
+
+    fn push(&mut self, label: &[u8]) -> Result<(), DnsError> {
+        if label.len() > MAX_LABEL_LEN {
+            return Err(DnsError::LabelTooLong);
+        }
+        if self.wire_len() + label.len() + 1 > MAX_NAME_LEN {
+            return Err(DnsError::NameTooLong);
+        }
+        self.labels.push(label.to_vec());
+        Ok(())
+    }
+
+    fn wire_len(&self) -> usize {
+        self.labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.labels.len() == other.labels.len() && self.is_subdomain_of(other)
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for label in &self.labels {
+            label.to_ascii_lowercase().hash(state);
+        }
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.labels.is_empty() {
+            return write!(f, ".");
+        }
+        for (i, label) in self.labels.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            for &byte in label {
+                match byte {
+                    b'.' | b'\\' => write!(f, "\\{}", byte as char)?,
+                    0x21..=0x7e => write!(f, "{}", byte as char)?,
+                    _ => write!(f, "\\{:03}", byte)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Name {
+    type Err = String;
+
+    /// Parses a dotted name, with or without the trailing dot
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut name = Name::root();
+        let s = s.strip_suffix('.').unwrap_or(s);
+        if s.is_empty() {
+            return Ok(name);
+        }
+        for label in s.split('.') {
+            if label.is_empty() {
+                return Err(format!("Invalid name: {}", s));
+            }
+            name.push(label.as_bytes()).map_err(|e| format!("Invalid name {}: {}", s, e))?;
+        }
+        Ok(name)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Header {
+    pub id: u16,
+    pub response: bool,
+    pub opcode: u8,
+    pub authoritative: bool,
+    pub truncated: bool,
+    pub recursion_desired: bool,
+    pub recursion_available: bool,
+    pub authentic_data: bool,
+    pub checking_disabled: bool,
+    pub rcode: u8,
+}
+
+impl Header {
+    fn parse(buffer: &[u8]) -> Header {
+        Header {
+            id: u16::from_be_bytes([buffer[0], buffer[1]]),
+            response: buffer[2] & 0x80 != 0,
+            opcode: (buffer[2] >> 3) & 0x0f,
+            authoritative: buffer[2] & 0x04 != 0,
+            truncated: buffer[2] & 0x02 != 0,
+            recursion_desired: buffer[2] & 0x01 != 0,
+            recursion_available: buffer[3] & 0x80 != 0,
+            authentic_data: buffer[3] & 0x20 != 0,
+            checking_disabled: buffer[3] & 0x10 != 0,
+            rcode: buffer[3] & 0x0f,
+        }
+    }
+
+    fn flags(&self) -> [u8; 2] {
+        let bit = |set: bool, mask: u8| if set { mask } else { 0 };
+        [
+            bit(self.response, 0x80)
+                | (self.opcode & 0x0f) << 3
+                | bit(self.authoritative, 0x04)
+                | bit(self.truncated, 0x02)
+                | bit(self.recursion_desired, 0x01),
+            bit(self.recursion_available, 0x80)
+                | bit(self.authentic_data, 0x20)
+                | bit(self.checking_disabled, 0x10)
+                | (self.rcode & 0x0f),
+        ]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Question {
+    pub name: Name,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Soa {
+    pub mname: Name,
+    pub rname: Name,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+}
+
+/// Record data. Types whose data holds names are decoded, so the names can
+/// be compressed again in another message; the rest is kept as it came.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ns(Name),
+    Cname(Name),
+    Ptr(Name),
+    Mx(u16, Name),
+    Soa(Soa),
+    Other(Vec<u8>),
+}
+
+impl RData {
+    /// Parses record data written the way zone files do, e.g. `10 mail.example.com` for MX
+    pub fn from_text(rtype: u16, s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let invalid = || format!("Invalid {} data: {}", type_name(rtype), s);
+        match rtype {
+            TYPE_A => s.parse().map(RData::A).map_err(|_| invalid()),
+            TYPE_AAAA => s.parse().map(RData::Aaaa).map_err(|_| invalid()),
+            TYPE_NS => s.parse().map(RData::Ns),
+            TYPE_CNAME => s.parse().map(RData::Cname),
+            TYPE_PTR => s.parse().map(RData::Ptr),
+            TYPE_MX => {
+                let (preference, exchange) = s.split_once(char::is_whitespace).ok_or_else(invalid)?;
+                Ok(RData::Mx(preference.parse().map_err(|_| invalid())?, exchange.trim().parse()?))
+            }
+            TYPE_TXT => {
+                // Character strings hold up to 255 bytes each, longer text is split over several
+                let text = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')).unwrap_or(s);
+                let mut data = Vec::new();
+                for chunk in text.as_bytes().chunks(255) {
+                    data.push(chunk.len() as u8);
+                    data.extend(chunk);
+                }
+                if data.is_empty() {
+                    data.push(0);
+                }
+                Ok(RData::Other(data))
+            }
+            _ => Err(format!("Cannot read {} data from the config", type_name(rtype))),
+        }
+    }
+}
+
+/// Record type by mnemonic, such as `AAAA`, or in the `TYPE<n>` form of RFC 3597
+pub fn parse_type(s: &str) -> Option<u16> {
+    TYPE_NAMES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(s))
+        .map(|(_, rtype)| *rtype)
+        .or_else(|| s.get(..4).filter(|prefix| prefix.eq_ignore_ascii_case("TYPE")).and_then(|_| s[4..].parse().ok()))
+}
+
+/// Mnemonic of a record type for logs, `TYPE<n>` for the ones without one here
+pub fn type_name(rtype: u16) -> String {
+    match TYPE_NAMES.iter().find(|(_, known)| *known == rtype) {
+        Some((name, _)) => name.to_string(),
+        None => format!("TYPE{}", rtype),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub name: Name,
+    pub rtype: u16,
+    pub class: u16,
+    pub ttl: u32,
+    pub data: RData,
+}
+
+/// A DNS message, RFC 1035 section 4
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Message {
+    pub header: Header,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Record>,
+    pub authority: Vec<Record>,
+    pub additional: Vec<Record>,
+}
+
+impl Message {
+    pub fn parse(buffer: &[u8]) -> Result<Self, DnsError> {
+        if buffer.len() < HEADER_LEN {
+            return Err(DnsError::Truncated(buffer.len()));
+        }
+        let mut reader = Reader { buffer, pos: HEADER_LEN };
+        let count = |at: usize| u16::from_be_bytes([buffer[at], buffer[at + 1]]);
+
+        let mut message = Message {
+            header: Header::parse(buffer),
+            ..Default::default()
+        };
+        for _ in 0..count(4) {
+            message.questions.push(Question {
+                name: reader.name()?,
+                qtype: reader.u16()?,
+                qclass: reader.u16()?,
+            });
+        }
+        for _ in 0..count(6) {
+            message.answers.push(reader.record()?);
+        }
+        for _ in 0..count(8) {
+            message.authority.push(reader.record()?);
+        }
+        for _ in 0..count(10) {
+            message.additional.push(reader.record()?);
+        }
+        Ok(message)
+    }
+
+    /// Starts a response to this query, echoing its ID, opcode, flags the client set and questions
+    pub fn response(&self) -> Message {
+        Message {
+            header: Header {
+                id: self.header.id,
+                response: true,
+                opcode: self.header.opcode,
+                recursion_desired: self.header.recursion_desired,
+                checking_disabled: self.header.checking_disabled,
+                ..Default::default()
+            },
+            questions: self.questions.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Largest response the sender takes over UDP, as its EDNS record says, RFC 6891 section 6.2.5
+    pub fn max_udp_size(&self) -> usize {
+        self.opt().map_or(UDP_SIZE, |opt| usize::from(opt.class).max(UDP_SIZE))
+    }
+
+    /// Answers EDNS in kind, RFC 6891 section 7: with an OPT record giving our own UDP size if
+    /// the query had one, without any otherwise.
+    ///
+    /// An OPT record already in the response, from an upstream, only keeps its extended rcode.
+    /// The DNSSEC OK bit is copied from the query, RFC 3225 section 3.
+    pub fn set_edns(&mut self, query: &Message, udp_size: u16) {
+        let extended_rcode = self.opt().map_or(0, |opt| opt.ttl & 0xff00_0000);
+        self.additional.retain(|record| record.rtype != TYPE_OPT);
+        if let Some(opt) = query.opt() {
+            self.additional.push(Record {
+                name: Name::root(),
+                rtype: TYPE_OPT,
+                class: udp_size,
+                ttl: extended_rcode | (opt.ttl & EDNS_DO),
+                data: RData::Other(Vec::new()),
+            });
+        }
+    }
+
+    fn opt(&self) -> Option<&Record> {
+        self.additional.iter().find(|record| record.rtype == TYPE_OPT)
+    }
+
+    /// Builds a FORMERR response to a query too broken to parse, as long as its header is intact
+    pub fn format_error(query: &[u8]) -> Option<Message> {
+        if query.len() < HEADER_LEN {
+            return None;
+        }
+        let header = Header::parse(query);
+        if header.response {
+            return None;
+        }
+        let mut response = Message {
+            header,
+            ..Default::default()
+        }
+        .response();
+        response.header.rcode = RCODE_FORMERR;
+        Some(response)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut writer = Writer {
+            buffer: Vec::with_capacity(512),
+            names: HashMap::new(),
+        };
+        writer.buffer.extend(&self.header.id.to_be_bytes());
+        writer.buffer.extend(&self.header.flags());
+        for len in [self.questions.len(), self.answers.len(), self.authority.len(), self.additional.len()] {
+            writer.buffer.extend(&(len as u16).to_be_bytes());
+        }
+
+        for question in &self.questions {
+            writer.name(&question.name);
+            writer.buffer.extend(&question.qtype.to_be_bytes());
+            writer.buffer.extend(&question.qclass.to_be_bytes());
+        }
+        for record in self.answers.iter().chain(&self.authority).chain(&self.additional) {
+            writer.record(record);
+        }
+        writer.buffer
+    }
+}
+
+struct Reader<'a> {
+    buffer: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Result<&[u8], DnsError> {
+        let bytes = self.buffer.get(self.pos..self.pos + len).ok_or(DnsError::Truncated(self.buffer.len()))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, DnsError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, DnsError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // Every pointer has to go further back than the one before, so a pointer loop cannot run forever
+    fn name(&mut self) -> Result<Name, DnsError> {
+        let truncated = DnsError::Truncated(self.buffer.len());
+        let mut name = Name::root();
+        let mut pos = self.pos;
+        let mut limit = self.pos;
+        let mut jumped = false;
+        loop {
+            let len = *self.buffer.get(pos).ok_or(truncated)?;
+            match len & 0xc0 {
+                0x00 if len == 0 => {
+                    pos += 1;
+                    break;
+                }
+                0x00 => {
+                    let label = self.buffer.get(pos + 1..pos + 1 + len as usize).ok_or(truncated)?;
+                    name.push(label)?;
+                    pos += 1 + len as usize;
+                }
+                0xc0 => {
+                    let low = *self.buffer.get(pos + 1).ok_or(truncated)?;
+                    let target = ((len as usize & 0x3f) << 8) | low as usize;
+                    if target >= limit {
+                        return Err(DnsError::BadPointer(target));
+                    }
+                    if !jumped {
+                        self.pos = pos + 2;
+                        jumped = true;
+                    }
+                    limit = target;
+                    pos = target;
+                }
+                _ => return Err(DnsError::BadLabelType(len)),
+            }
+        }
+        if !jumped {
+            self.pos = pos;
+        }
+        Ok(name)
+    }
+
+    fn record(&mut self) -> Result<Record, DnsError> {
+        let name = self.name()?;
+        let rtype = self.u16()?;
+        let class = self.u16()?;
+        let ttl = self.u32()?;
+        let len = self.u16()? as usize;
+        let end = self.pos + len;
+        if end > self.buffer.len() {
+            return Err(DnsError::Truncated(self.buffer.len()));
+        }
+
+        let data = match rtype {
+            TYPE_A if len == 4 => {
+                let bytes = self.bytes(4)?;
+                RData::A(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]))
+            }
+            TYPE_AAAA if len == 16 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(self.bytes(16)?);
+                RData::Aaaa(Ipv6Addr::from(octets))
+            }
+            TYPE_A | TYPE_AAAA => return Err(DnsError::MalformedRecord(rtype)),
+            TYPE_NS => RData::Ns(self.name()?),
+            TYPE_CNAME => RData::Cname(self.name()?),
+            TYPE_PTR => RData::Ptr(self.name()?),
+            TYPE_MX => RData::Mx(self.u16()?, self.name()?),
+            TYPE_SOA => RData::Soa(Soa {
+                mname: self.name()?,
+                rname: self.name()?,
+                serial: self.u32()?,
+                refresh: self.u32()?,
+                retry: self.u32()?,
+                expire: self.u32()?,
+                minimum: self.u32()?,
+            }),
+            _ => RData::Other(self.bytes(len)?.to_vec()),
+        };
+        if self.pos != end {
+            return Err(DnsError::MalformedRecord(rtype));
+        }
+        Ok(Record { name, rtype, class, ttl, data })
+    }
+}
+
+struct Writer {
+    buffer: Vec<u8>,
+    // Where each name written so far, and each of its suffixes, can be pointed to
+    names: HashMap<Name, usize>,
+}
+
+impl Writer {
+    fn name(&mut self, name: &Name) {
+        for (i, label) in name.labels().iter().enumerate() {
+            let suffix = name.suffix(i);
+            if let Some(&offset) = self.names.get(&suffix) {
+                self.buffer.extend(&(0xc000 | offset as u16).to_be_bytes());
+                return;
+            }
+            if self.buffer.len() <= MAX_POINTER {
+                self.names.insert(suffix, self.buffer.len());
+            }
+            self.buffer.push(label.len() as u8);
+            self.buffer.extend(label);
+        }
+        self.buffer.push(0);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.buffer.extend(&value.to_be_bytes());
+    }
+
+    fn record(&mut self, record: &Record) {
+        self.name(&record.name);
+        self.buffer.extend(&record.rtype.to_be_bytes());
+        self.buffer.extend(&record.class.to_be_bytes());
+        self.u32(record.ttl);
+
+        // Length goes in once the data is written, compression makes it hard to know beforehand
+        let len_at = self.buffer.len();
+        self.buffer.extend(&[0, 0]);
+        match &record.data {
+            RData::A(addr) => self.buffer.extend(&addr.octets()),
+            RData::Aaaa(addr) => self.buffer.extend(&addr.octets()),
+            RData::Ns(name) | RData::Cname(name) | RData::Ptr(name) => self.name(name),
+            RData::Mx(preference, name) => {
+                self.buffer.extend(&preference.to_be_bytes());
+                self.name(name);
+            }
+            RData::Soa(soa) => {
+                self.name(&soa.mname);
+                self.name(&soa.rname);
+                for value in [soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum] {
+                    self.u32(value);
+                }
+            }
+            RData::Other(data) => self.buffer.extend(data),
+        }
+        let len = (self.buffer.len() - len_at - 2) as u16;
+        self.buffer[len_at..len_at + 2].copy_from_slice(&len.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn name(s: &str) -> Name {
+        s.parse().unwrap()
+    }
+
+    fn record(owner: &str, rtype: u16, data: RData) -> Record {
+        Record {
+            name: name(owner),
+            rtype,
+            class: CLASS_IN,
+            ttl: 300,
+            data,
+        }
+    }
+
+    fn sample() -> Message {
+        let mut query = Message::default();
+        query.header.id = 0xbeef;
+        query.header.recursion_desired = true;
+        query.questions.push(Question {
+            name: name("www.example.com"),
+            qtype: TYPE_A,
+            qclass: CLASS_IN,
+        });
+
+        let mut response = query.response();
+        response.header.authoritative = true;
+        response.header.rcode = RCODE_NXDOMAIN;
+        response.answers = vec![
+            record("www.example.com", TYPE_CNAME, RData::Cname(name("web.example.com"))),
+            record("web.example.com", TYPE_A, RData::A(Ipv4Addr::new(192, 0, 2, 1))),
+            record("web.example.com", TYPE_AAAA, RData::Aaaa("2001:db8::1".parse().unwrap())),
+            record("example.com", TYPE_MX, RData::Mx(10, name("mail.example.com"))),
+            record("example.com", TYPE_TXT, RData::from_text(TYPE_TXT, "\"hello\"").unwrap()),
+        ];
+        response.authority.push(record(
+            "example.com",
+            TYPE_SOA,
+            RData::Soa(Soa {
+                mname: name("ns.example.com"),
+                rname: name("hostmaster.example.com"),
+                serial: 1,
+                refresh: 2,
+                retry: 3,
+                expire: 4,
+                minimum: 5,
+            }),
+        ));
+        response.additional.push(record("ns.example.com", TYPE_PTR, RData::Ptr(name("example.com"))));
+        response
+    }
+
+    // Header of a query with one question, followed by whatever the test puts in
+    fn with_question(name: &[u8]) -> Vec<u8> {
+        let mut buffer = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        buffer.extend(name);
+        buffer.extend(&[0, 1, 0, 1]);
+        buffer
+    }
+
+    #[test]
+    fn round_trip() {
+        let message = sample();
+        let serialized = message.serialize();
+        assert_eq!(Message::parse(&serialized).unwrap(), message);
+    }
+
+    #[test]
+    fn names_are_compressed() {
+        // `example` is only spelled out once, in the question, record names and data point back to it
+        let serialized = sample().serialize();
+        assert_eq!(serialized.windows(7).filter(|window| window == b"example").count(), 1);
+    }
+
+    #[test]
+    fn pointer_loop_is_rejected() {
+        // The question name points at itself
+        let buffer = with_question(&[0xc0, 12]);
+        assert_eq!(Message::parse(&buffer), Err(DnsError::BadPointer(12)));
+    }
+
+    #[test]
+    fn forward_pointer_is_rejected() {
+        let buffer = with_question(&[3, b'w', b'w', b'w', 0xc0, 30]);
+        assert_eq!(Message::parse(&buffer), Err(DnsError::BadPointer(30)));
+    }
+
+    #[test]
+    fn mutual_pointers_are_rejected() {
+        // The answer's name points back to the question, whose name points forward to the answer
+        let mut buffer = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+        buffer.extend(&[0xc0, 18, 0, 1, 0, 1]);
+        buffer.extend(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert!(matches!(Message::parse(&buffer), Err(DnsError::BadPointer(_))));
+    }
+
+    #[test]
+    fn truncated_input_gets_formerr() {
+        let serialized = sample().serialize();
+        let mut query = sample();
+        query.header.response = false;
+        let query = query.serialize();
+        for len in 0..serialized.len() {
+            assert!(Message::parse(&serialized[..len]).is_err(), "parsed {} of {} bytes", len, serialized.len());
+            match Message::format_error(&query[..len.min(query.len())]) {
+                Some(response) => {
+                    assert!(len >= HEADER_LEN);
+                    assert_eq!(response.header.rcode, RCODE_FORMERR);
+                    assert_eq!(response.header.id, 0xbeef);
+                }
+                None => assert!(len < HEADER_LEN),
+            }
+        }
+    }
+
+    #[test]
+    fn responses_get_no_formerr() {
+        assert!(Message::format_error(&sample().serialize()).is_none());
+    }
+
+    #[test]
+    fn names_compare_case_insensitively() {
+        assert_eq!(name("WWW.Example.COM"), name("www.example.com"));
+        assert_ne!(name("www.example.com"), name("example.com"));
+        assert_ne!(name("www.example.com"), name("www.example.org"));
+        assert_eq!(name("example.com."), name("example.com"));
+
+        let names: HashSet<Name> = [name("Printer.Guest")].into_iter().collect();
+        assert!(names.contains(&name("printer.guest")));
+    }
+
+    #[test]
+    fn subdomains() {
+        assert!(name("a.b.Example.com").is_subdomain_of(&name("example.COM")));
+        assert!(name("example.com").is_subdomain_of(&name("example.com")));
+        assert!(!name("example.com").is_subdomain_of(&name("www.example.com")));
+        assert!(!name("badexample.com").is_subdomain_of(&name("example.com")));
+    }
+
+    #[test]
+    fn reverse_names() {
+        assert_eq!(Name::reverse("10.9.0.1".parse().unwrap()), name("1.0.9.10.in-addr.arpa"));
+        assert_eq!(
+            Name::reverse("2001:db8::1".parse().unwrap()),
+            name("1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa")
+        );
+    }
+
+    #[test]
+    fn edns_size() {
+        let mut query = sample();
+        assert_eq!(query.max_udp_size(), UDP_SIZE);
+        query.additional.push(Record {
+            name: Name::root(),
+            rtype: TYPE_OPT,
+            class: 1232,
+            ttl: 0,
+            data: RData::Other(Vec::new()),
+        });
+        assert_eq!(query.max_udp_size(), 1232);
+    }
+
+    #[test]
+    fn edns_is_answered_in_kind() {
+        let opt = |class, ttl| Record {
+            name: Name::root(),
+            rtype: TYPE_OPT,
+            class,
+            ttl,
+            data: RData::Other(Vec::new()),
+        };
+        let mut query = sample();
+        let mut response = query.response();
+        response.additional.push(opt(1232, 0));
+        response.set_edns(&query, 4096);
+        assert!(response.additional.is_empty());
+
+        // The upstream's size goes, its extended rcode stays, DO comes from the query
+        query.additional.push(opt(1232, EDNS_DO));
+        response.additional.push(opt(1232, 0x0100_0000));
+        response.set_edns(&query, 4096);
+        assert_eq!(response.additional.len(), 1);
+        assert_eq!(response.additional[0].class, 4096);
+        assert_eq!(response.additional[0].ttl, 0x0100_0000 | EDNS_DO);
+
+        let parsed = Message::parse(&response.serialize()).unwrap();
+        assert_eq!(parsed.max_udp_size(), 4096);
+    }
+}
@@ -1,64 +1,276 @@
+pub mod cache;
+pub mod forward;
+pub mod garden;
+pub mod message;
+pub mod tcp;
+pub mod zone;
+
 use crate::{lock, receiver, server::*, server_state};
+use crate::server::iface;
+use crate::server::events::{self, LeaseEvent};
+use crate::server::registry::Client;
 use confee::conf::*;
-use std::net::{IpAddr, UdpSocket, SocketAddr};
+use cache::Cache;
+use forward::Forwarder;
+use garden::WalledGarden;
+use message::*;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr, UdpSocket, SocketAddr, SocketAddrV6, TcpListener};
 use std::io;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::sync::mpsc;
+use tcp::Connection;
+use zone::LocalZone;
+
+// Lifetime of the answers that point clients at the portal, short so they are gone soon after the client passes it
+const HIJACK_TTL: u32 = 10;
+
+// How often the walled garden and the hosts file are checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+// Room for UDP queries, EDNS ones with cookies or padding included
+const UDP_BUFFER_SIZE: usize = 4096;
+
+// Clients connected over TCP at once, beyond that new connections are turned away
+const MAX_CONNECTIONS: usize = 64;
+
+// Queries one TCP client may have waiting upstream, one sending more is dropped
+const MAX_QUERIES_IN_FLIGHT: usize = 16;
+
+// Where an answer goes back to
+#[derive(Clone, Copy)]
+enum Origin {
+    // The index of the socket the query came in on, and the client
+    Udp(usize, SocketAddr),
+    // The connection the query came in on, and the client
+    Tcp(u64, SocketAddr),
+}
+
+impl Origin {
+    fn peer(&self) -> SocketAddr {
+        match self {
+            Origin::Udp(_, addr) | Origin::Tcp(_, addr) => *addr,
+        }
+    }
+}
 
 pub struct Dns {
     addr: IpAddr,
+    addr6: Option<Ipv6Addr>,
+    // Where a link-local addr6 lives
+    iface: String,
     port: u16,
+    // What names answer for types other than A and AAAA
+    hijack_records: Vec<(u16, RData)>,
+    // Resolvers that answer for clients past the portal
+    upstreams: Vec<SocketAddr>,
+    upstream_timeout: Duration,
+    // How long a TCP client may keep its connection without using it
+    tcp_idle_timeout: Duration,
+    // Forwarded answers, so repeated lookups stay off the upstreams
+    cache: Mutex<Cache>,
+    walled_garden: Mutex<WalledGarden>,
+    // Names answered the same for everyone, before hijacking or forwarding
+    local_zone: Mutex<LocalZone>,
     pub state: ServerState,
 }
 
 impl Server for Dns {
     fn create(conf: &Conf) -> Self {
+        let domain = Some(conf["dns_domain"].trim()).filter(|domain| !domain.is_empty()).map(str::parse::<Name>);
         let mut dns = Dns {
             addr: conf.get("link_addr").unwrap(),
+            addr6: conf.get("link_addr6"),
+            iface: conf["link_iface"].clone(),
             port: conf.get("dns_port").unwrap(),
+            hijack_records: Vec::new(),
+            upstreams: Vec::new(),
+            upstream_timeout: Duration::from_millis(conf.get("dns_upstream_timeout").unwrap()),
+            tcp_idle_timeout: Duration::from_millis(conf.get("dns_tcp_idle_timeout").unwrap()),
+            cache: Mutex::new(Cache::new(
+                conf.get("dns_cache_size").unwrap(),
+                conf.get("dns_cache_min_ttl").unwrap(),
+                conf.get("dns_cache_max_ttl").unwrap(),
+            )),
+            walled_garden: Mutex::new(WalledGarden::new(&conf["dns_walled_garden"])),
+            local_zone: Mutex::new(LocalZone::new(&conf["dns_hosts_file"], domain.clone().and_then(Result::ok))),
             state: server_state!(),
         };
         dns.state.prefix = String::from("dns");
+        dns.load_hijack_records(&conf["dns_hijack_records"]);
+        for upstream in conf["dns_upstream"].split(',').map(str::trim).filter(|upstream| !upstream.is_empty()) {
+            match forward::parse_upstream(upstream) {
+                Ok(upstream) => dns.upstreams.push(upstream),
+                Err(e) => dns.log(&format!("Ignoring upstream {}: {}", upstream, e)),
+            }
+        }
+        if let Some(Err(e)) = domain {
+            dns.log(&format!("Not registering DHCP hostnames: {}", e));
+        }
+        dns.load_zone(&conf["dns_zone"]);
+        dns.reload_walled_garden();
+        dns.reload_local_zone();
         dns
     }
 
     fn mainloop(&self) {
-        let socket_addr = SocketAddr::new(self.addr, self.port);
-        let socket = UdpSocket::bind(socket_addr).expect(&format!("{}: Could not bind to address", self.state.prefix));
-        socket.set_nonblocking(true).expect(&format!("{}: Failed to set non-blocking", self.state.prefix));
+        // Also answer over IPv6, which is where router advertisements point clients
+        let mut addrs = vec![SocketAddr::new(self.addr, self.port)];
+        if let Some(addr6) = self.addr6 {
+            match self.scope_id(addr6) {
+                Ok(scope_id) => addrs.push(SocketAddr::V6(SocketAddrV6::new(addr6, self.port, 0, scope_id))),
+                Err(e) => self.log(&format!("Not answering on {}, no scope for it on {}: {}", addr6, self.iface, e)),
+            }
+        }
+        let sockets: Vec<UdpSocket> = addrs
+            .iter()
+            .map(|addr| {
+                let socket = UdpSocket::bind(addr).unwrap_or_else(|_| panic!("{}: Could not bind to address", self.state.prefix));
+                socket.set_nonblocking(true).unwrap_or_else(|_| panic!("{}: Failed to set non-blocking", self.state.prefix));
+                socket
+            })
+            .collect();
+        // For answers too large for UDP, and clients that only ask over TCP
+        let listeners: Vec<TcpListener> = addrs
+            .iter()
+            .map(|addr| {
+                let listener = TcpListener::bind(addr).unwrap_or_else(|_| panic!("{}: Could not bind to address", self.state.prefix));
+                listener.set_nonblocking(true).unwrap_or_else(|_| panic!("{}: Failed to set non-blocking", self.state.prefix));
+                listener
+            })
+            .collect();
+        let mut connections: HashMap<u64, Connection> = HashMap::new();
+        let mut next_connection = 0;
+
+        let mut forwarder = if self.upstreams.is_empty() {
+            self.log("No dns_upstream, every client gets the portal address");
+            None
+        } else {
+            let forwarder = Forwarder::new(self.upstreams.clone(), self.upstream_timeout)
+                .unwrap_or_else(|e| panic!("{}: Could not open upstream socket: {}", self.state.prefix, e));
+            Some(forwarder)
+        };
 
+        // Subscribe before looking at the registry, so no lease slips through in between
+        let lease_events = events::subscribe();
+        for client in registry::clients() {
+            self.register_hostname(&client);
+        }
+
+        let mut last_reload = Instant::now();
         loop {
             lock!(receiver!(self), rx => {
-                if let Ok(_) = rx.try_recv() {
+                if rx.try_recv().is_ok() {
                     self.log("Stop signal received. Shutting down.");
                     break;
                 }
             });
 
-            let mut buffer = [0; 512];
-            match socket.recv_from(&mut buffer) {
-                Ok((ref mut n, addr)) => {
-                    self.log(&format!("New query from {}", addr));
-                    self.log(&format!("Received {} bytes of data:\n{}", n, self.format_bytes_as_hex(&buffer, *n)));
-                    let response = self.create_response(&buffer, *n);
-                    match socket.send_to(&response[..], addr) {
-                        Ok(sent_bytes) => {
-                            self.log(&format!("Sent {} bytes to {}:\n{}", sent_bytes, addr, self.format_bytes_as_hex(&response[..], sent_bytes)));
+            for event in lease_events.try_iter() {
+                match event {
+                    LeaseEvent::Granted(client) | LeaseEvent::Renewed(client) => self.register_hostname(&client),
+                    LeaseEvent::Released(client) | LeaseEvent::Expired(client) => self.unregister_hostname(&client),
+                }
+            }
+
+            for (index, socket) in sockets.iter().enumerate() {
+                let mut buffer = [0; UDP_BUFFER_SIZE];
+                match socket.recv_from(&mut buffer) {
+                    Ok((ref mut n, addr)) => {
+                        self.log(&format!("New query from {}", registry::describe(addr.ip())));
+                        self.log(&format!("Received {} bytes of data:\n{}", n, self.format_bytes_as_hex(&buffer, *n)));
+                        if let Some(response) = self.handle_query(&buffer[..*n], Origin::Udp(index, addr), &mut forwarder) {
+                            self.send_response(socket, &response, addr);
                         }
-                        Err(e) => {
-                            self.log(&format!("Failed to send response to {}: {}", addr, e));
+                        self.log("Waiting for queries...");
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        // No connections available, continue looping
+                    }
+                    Err(e) => {
+                        self.log(&format!("Error receiving query: {}", e));
+                    }
+                }
+            }
+
+            for listener in &listeners {
+                match listener.accept() {
+                    Ok((stream, addr)) if connections.len() < MAX_CONNECTIONS => match Connection::new(stream, addr) {
+                        Ok(connection) => {
+                            self.log(&format!("New connection from {}", registry::describe(addr.ip())));
+                            connections.insert(next_connection, connection);
+                            next_connection += 1;
                         }
+                        Err(e) => self.log(&format!("Error setting up connection from {}: {}", addr, e)),
+                    },
+                    Ok((_, addr)) => {
+                        self.log(&format!("Turning away connection from {}, {} clients connected already", addr, MAX_CONNECTIONS));
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        // No connections available, continue looping
+                    }
+                    Err(e) => {
+                        self.log(&format!("Error accepting connection: {}", e));
                     }
-                    self.log("Waiting for queries...");
                 }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    // No connections available, continue looping
+            }
+
+            for (id, connection) in connections.iter_mut() {
+                for query in connection.receive() {
+                    if connection.is_closed() {
+                        break;
+                    }
+                    let addr = connection.peer();
+                    let in_flight = forwarder
+                        .as_ref()
+                        .map_or(0, |forwarder| forwarder.waiting_for(|origin| matches!(origin, Origin::Tcp(other, _) if other == id)));
+                    if in_flight >= MAX_QUERIES_IN_FLIGHT {
+                        connection.close(&format!("more than {} queries waiting for upstream answers", MAX_QUERIES_IN_FLIGHT));
+                        break;
+                    }
+                    self.log(&format!("New query from {} over TCP", registry::describe(addr.ip())));
+                    self.log(&format!("Received {} bytes of data:\n{}", query.len(), self.format_bytes_as_hex(&query, query.len())));
+                    if let Some(response) = self.handle_query(&query, Origin::Tcp(*id, addr), &mut forwarder) {
+                        self.send_tcp_response(connection, &response);
+                    }
                 }
-                Err(e) => {
-                    self.log(&format!("Error receiving query: {}", e));
+                connection.flush();
+            }
+
+            if last_reload.elapsed() >= RELOAD_INTERVAL {
+                self.reload_walled_garden();
+                self.reload_local_zone();
+                last_reload = Instant::now();
+            }
+
+            if let Some(forwarder) = &mut forwarder {
+                for (origin, query, response) in forwarder.poll(|message| self.log(message)) {
+                    lock!(self.cache, cache => cache.insert(&response));
+                    let response = self.fit(&query, response, origin);
+                    match origin {
+                        Origin::Udp(index, addr) => self.send_response(&sockets[index], &response, addr),
+                        Origin::Tcp(id, _) => {
+                            if let Some(connection) = connections.get_mut(&id) {
+                                self.send_tcp_response(connection, &response);
+                            }
+                        }
+                    }
                 }
             }
 
+            connections.retain(|id, connection| {
+                let waiting = forwarder
+                    .as_ref()
+                    .is_some_and(|forwarder| forwarder.waiting_for(|origin| matches!(origin, Origin::Tcp(other, _) if other == id)) > 0);
+                match connection.close_reason(self.tcp_idle_timeout, waiting) {
+                    Some(reason) => {
+                        self.log(&format!("Closing connection from {}: {}", connection.peer(), reason));
+                        false
+                    }
+                    None => true,
+                }
+            });
+
             thread::sleep(Duration::from_millis(10));
         }
 
@@ -67,46 +279,272 @@ impl Server for Dns {
 }
 
 impl Dns {
-    fn create_response(&self, request: &[u8], size: usize) -> Vec<u8> {
-        let mut response = Vec::new();
-    
-        // Copy the request header (transaction ID, flags, etc.)
-        response.extend_from_slice(&request[0..12]);
-
-        // Set the response flag (standard response, no error)
-        response[2] = 0x80; // Response, recursion available
-        if (request[2] & 0x01) != 0 {
-            response[2] |= 0x01;
-        }
-        response[3] = 0x00; // No error
-        response[7] = 0x01; // 1 Answer
-        response[11] = 0x00; // No Additional section
-
-        // Copy the question section
-        if let Some(end) = request[12..].iter().position(|&b| b == 0x00) {
-            let question_end = 12 + end + 5; // +1 for null byte, +4 for type and class
-            response.extend_from_slice(&request[12..question_end]);
-        }
-
-        // Append the answer section
-        // Name is a pointer to the domain in the question section (0xC00C)
-        response.extend_from_slice(&[0xC0, 0x0C]);
-
-        // Type A (IPv4), Class IN (Internet)
-        response.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
-
-        // TTL (time-to-live) in seconds (300 here)
-        response.extend_from_slice(&[0x00, 0x00, 0x01, 0x2C]);
-
-        // Data length (4 bytes for IPv4 address)
-        response.extend_from_slice(&[0x00, 0x04]);
-    
-        // Include the IPv4 address
-        if let IpAddr::V4(ipv4_addr) = self.addr {
-            response.extend_from_slice(&ipv4_addr.octets()); // Append the IP address
-        }
-    
-        response // Return the constructed response
+    // A link-local address can only be bound on the interface it belongs to, RFC 4007 section 6
+    fn scope_id(&self, addr6: Ipv6Addr) -> io::Result<u32> {
+        if !addr6.is_unicast_link_local() {
+            return Ok(0);
+        }
+        iface::interface_index(&self.iface).map(|ifindex| ifindex as u32)
+    }
+
+    // Malformed queries get a FORMERR if their header survived, responses sent to us are dropped.
+    // Clients past the portal are answered later, once an upstream resolver did.
+    fn handle_query(&self, buffer: &[u8], origin: Origin, forwarder: &mut Option<Forwarder<Origin>>) -> Option<Vec<u8>> {
+        let addr = origin.peer();
+        let query = match Message::parse(buffer) {
+            Ok(query) if !query.header.response => query,
+            Ok(_) => {
+                self.log(&format!("Ignoring response from {}", addr));
+                return None;
+            }
+            Err(e) => {
+                self.log(&format!("Malformed query from {}: {}", addr, e));
+                return Message::format_error(buffer).map(|response| response.serialize());
+            }
+        };
+
+        let response = self.answer(&query, origin, forwarder)?;
+        Some(self.fit(&query, response, origin))
+    }
+
+    // Largest answer the client takes: what it says over UDP, the most a length prefix holds over TCP
+    fn max_size(query: &Message, origin: Origin) -> usize {
+        match origin {
+            Origin::Udp(..) => query.max_udp_size(),
+            Origin::Tcp(..) => usize::from(u16::MAX),
+        }
+    }
+
+    // Answers too large for the client are cut down to the header and OPT record, so it asks again over TCP
+    fn fit(&self, query: &Message, mut response: Message, origin: Origin) -> Vec<u8> {
+        response.set_edns(query, UDP_BUFFER_SIZE as u16);
+        let serialized = response.serialize();
+        if serialized.len() <= Self::max_size(query, origin) {
+            return serialized;
+        }
+        self.log(&format!("Truncating {} byte answer for {}", serialized.len(), origin.peer()));
+        response.header.truncated = true;
+        response.answers.clear();
+        response.authority.clear();
+        response.additional.retain(|record| record.rtype == TYPE_OPT);
+        response.serialize()
+    }
+
+    fn answer(&self, query: &Message, origin: Origin, forwarder: &mut Option<Forwarder<Origin>>) -> Option<Message> {
+        let addr = origin.peer();
+        if let Some(response) = self.local_response(query) {
+            return Some(response);
+        }
+        if let Some(forwarder) = forwarder.as_mut().filter(|_| self.forwards(query, addr)) {
+            if let Some(response) = lock!(self.cache, cache => cache.get(query)) {
+                self.log("Answering from cache");
+                return Some(response);
+            }
+            match forwarder.forward(query.clone(), origin, Self::max_size(query, origin)) {
+                Ok(()) => return None,
+                Err(e) => {
+                    self.log(&format!("Not forwarding query from {}: {}", addr, e));
+                    let mut response = query.response();
+                    response.header.rcode = RCODE_SERVFAIL;
+                    response.header.recursion_available = true;
+                    return Some(response);
+                }
+            }
+        }
+        Some(self.create_response(query))
+    }
+
+    // Clients past the portal resolve anything, everyone else only what is in the walled garden
+    fn forwards(&self, query: &Message, addr: SocketAddr) -> bool {
+        if registry::is_authenticated(addr.ip()) {
+            return true;
+        }
+        lock!(self.walled_garden, walled_garden => {
+            !query.questions.is_empty() && query.questions.iter().all(|question| walled_garden.contains(&question.name))
+        })
+    }
+
+    fn reload_walled_garden(&self) {
+        let messages = lock!(self.walled_garden, walled_garden => walled_garden.reload());
+        for message in messages {
+            self.log(&message);
+        }
+    }
+
+    fn reload_local_zone(&self) {
+        let messages = lock!(self.local_zone, local_zone => local_zone.reload());
+        for message in messages {
+            self.log(&message);
+        }
+    }
+
+    // A client that stopped sending a hostname loses its name
+    fn register_hostname(&self, client: &Client) {
+        let Some(hostname) = &client.info.hostname else {
+            self.unregister_hostname(client);
+            return;
+        };
+        match lock!(self.local_zone, local_zone => local_zone.add_lease(client.addr, hostname)) {
+            Ok(Some(name)) => self.log(&format!("{} resolves to {} for {}", name, client.addr, client)),
+            Ok(None) => {}
+            Err(e) => self.log(&format!("Not registering hostname of {}: {}", client, e)),
+        }
+    }
+
+    fn unregister_hostname(&self, client: &Client) {
+        if let Some(name) = lock!(self.local_zone, local_zone => local_zone.remove_lease(client.addr)) {
+            self.log(&format!("{} no longer resolves to {}", name, client.addr));
+        }
+    }
+
+    // Entries look like `portal.lilap A 10.9.0.1` or `lilap MX 10 portal.lilap`, separated by commas
+    fn load_zone(&self, value: &str) {
+        for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let result = lock!(self.local_zone, local_zone => local_zone.add_entry(entry));
+            if let Err(e) = result {
+                self.log(&format!("Ignoring zone entry {}: {}", entry, e));
+            }
+        }
+    }
+
+    // Answers from the local zone, for queries whose every name it knows or lies in the local domain.
+    // Names missing from the local domain do not exist, rather than going upstream or to the portal.
+    fn local_response(&self, query: &Message) -> Option<Message> {
+        if query.header.opcode != OPCODE_QUERY || query.questions.is_empty() {
+            return None;
+        }
+        let answers: Vec<Option<Vec<Record>>> = lock!(self.local_zone, local_zone => {
+            query
+                .questions
+                .iter()
+                .filter(|question| question.qclass == CLASS_IN)
+                .map(|question| match local_zone.lookup(&question.name, question.qtype) {
+                    Some(answers) => Some(Some(answers)),
+                    None if local_zone.is_local(&question.name) => Some(None),
+                    None => None,
+                })
+                .collect::<Option<_>>()
+        })?;
+        if answers.len() < query.questions.len() {
+            return None;
+        }
+
+        let mut response = query.response();
+        response.header.authoritative = true;
+        for (question, answers) in query.questions.iter().zip(answers) {
+            let Some(answers) = answers else {
+                response.header.rcode = RCODE_NXDOMAIN;
+                response.authority.push(Self::negative_soa(&question.name, zone::LOCAL_TTL));
+                continue;
+            };
+            if answers.is_empty() {
+                response.authority.push(Self::negative_soa(&question.name, zone::LOCAL_TTL));
+            }
+            response.answers.extend(answers);
+        }
+        Some(response)
+    }
+
+    fn send_response(&self, socket: &UdpSocket, response: &[u8], addr: SocketAddr) {
+        match socket.send_to(response, addr) {
+            Ok(sent_bytes) => {
+                self.log(&format!("Sent {} bytes to {}:\n{}", sent_bytes, addr, self.format_bytes_as_hex(response, sent_bytes)));
+            }
+            Err(e) => {
+                self.log(&format!("Failed to send response to {}: {}", addr, e));
+            }
+        }
+    }
+
+    fn send_tcp_response(&self, connection: &mut Connection, response: &[u8]) {
+        connection.send(response);
+        self.log(&format!("Sent {} bytes to {} over TCP:\n{}", response.len(), connection.peer(), self.format_bytes_as_hex(response, response.len())));
+    }
+
+    // Entries look like `TXT some text` or `MX 10 portal.lilap`, separated by commas
+    fn load_hijack_records(&mut self, value: &str) {
+        for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (rtype, data) = entry.split_once(char::is_whitespace).unwrap_or((entry, ""));
+            let record = parse_type(rtype)
+                .ok_or_else(|| format!("Unknown record type {}", rtype))
+                .and_then(|rtype| match rtype {
+                    TYPE_A | TYPE_AAAA => Err("A always points at the portal, AAAA never does".to_string()),
+                    _ => Ok((rtype, RData::from_text(rtype, data)?)),
+                });
+            match record {
+                Ok(record) => self.hijack_records.push(record),
+                Err(e) => self.log(&format!("Ignoring hijack record {}: {}", entry, e)),
+            }
+        }
+    }
+
+    fn create_response(&self, query: &Message) -> Message {
+        let mut response = query.response();
+        if query.header.opcode != OPCODE_QUERY {
+            response.header.rcode = RCODE_NOTIMP;
+            return response;
+        }
+
+        // We make up the answers ourselves, so they are authoritative
+        response.header.authoritative = true;
+        for question in &query.questions {
+            let answers = self.hijacked_answers(question);
+            if answers.is_empty() {
+                // NODATA: the name exists, just not with this type
+                response.authority.push(Self::negative_soa(&question.name, HIJACK_TTL));
+            }
+            response.answers.extend(answers);
+        }
+        response
+    }
+
+    // Every name leads to the portal
+    fn hijacked_answers(&self, question: &Question) -> Vec<Record> {
+        if question.qclass != CLASS_IN {
+            return Vec::new();
+        }
+        let data: Vec<RData> = match question.qtype {
+            TYPE_A => match self.addr {
+                IpAddr::V4(addr) => vec![RData::A(addr)],
+                IpAddr::V6(_) => Vec::new(),
+            },
+            // The portal only listens on IPv4, an AAAA answer would leave clients preferring IPv6 with nowhere to go
+            TYPE_AAAA => Vec::new(),
+            qtype => self
+                .hijack_records
+                .iter()
+                .filter(|(rtype, _)| *rtype == qtype)
+                .map(|(_, data)| data.clone())
+                .collect(),
+        };
+        data.into_iter()
+            .map(|data| Record {
+                name: question.name.clone(),
+                rtype: question.qtype,
+                class: CLASS_IN,
+                ttl: HIJACK_TTL,
+                data,
+            })
+            .collect()
+    }
+
+    // Lets resolvers cache the NODATA for as long as they would have cached an answer, RFC 2308 section 3
+    fn negative_soa(name: &Name, ttl: u32) -> Record {
+        Record {
+            name: name.clone(),
+            rtype: TYPE_SOA,
+            class: CLASS_IN,
+            ttl,
+            data: RData::Soa(Soa {
+                mname: name.clone(),
+                rname: Name::root(),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: ttl,
+            }),
+        }
     }
 
     fn format_bytes_as_hex(&self, buffer: &[u8], n: usize) -> String {
@@ -0,0 +1,148 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+// Received bytes held at once, a query of the largest size. The rest waits in the socket until they are handled.
+const MAX_INPUT: usize = 2 + u16::MAX as usize;
+
+// Answers queued for a client that does not take them, beyond that it is dropped. Fits two of the largest answers.
+const MAX_OUTPUT: usize = 2 * (2 + u16::MAX as usize);
+
+/// Prefixes a message with its length, as DNS over TCP wants it, RFC 1035 section 4.2.2
+pub fn frame(message: &[u8]) -> Vec<u8> {
+    let mut framed = (message.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(message);
+    framed
+}
+
+/// A client connection to the DNS server over TCP.
+///
+/// The client may send several queries without waiting for the answers,
+/// RFC 7766 section 6.2.1.1, which go back in whatever order they are ready.
+/// A client that lets its answers pile up is dropped rather than buffered for.
+pub struct Connection {
+    stream: TcpStream,
+    peer: SocketAddr,
+    // Received bytes not making a whole query yet
+    input: Vec<u8>,
+    // Answers the client did not take yet
+    output: Vec<u8>,
+    last_received: Instant,
+    // When the client last took some of the answers, or they started queueing up
+    last_sent: Instant,
+    // Why the connection is over, set once the client hung up or it broke
+    closed: Option<String>,
+    // The client is done sending, answers may still go out
+    finished: bool,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream, peer: SocketAddr) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        Ok(Connection {
+            stream,
+            peer,
+            input: Vec::new(),
+            output: Vec::new(),
+            last_received: Instant::now(),
+            last_sent: Instant::now(),
+            closed: None,
+            finished: false,
+        })
+    }
+
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Reads what the client sent, returning the queries that came in whole
+    pub fn receive(&mut self) -> Vec<Vec<u8>> {
+        let mut buffer = [0u8; 4096];
+        while !self.finished && self.closed.is_none() && self.input.len() < MAX_INPUT {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => self.finished = true,
+                Ok(n) => {
+                    self.input.extend_from_slice(&buffer[..n]);
+                    self.last_received = Instant::now();
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => self.closed = Some(e.to_string()),
+            }
+        }
+
+        let mut queries = Vec::new();
+        while self.input.len() >= 2 {
+            let len = u16::from_be_bytes([self.input[0], self.input[1]]) as usize;
+            if self.input.len() < 2 + len {
+                break;
+            }
+            queries.push(self.input[2..2 + len].to_vec());
+            self.input.drain(..2 + len);
+        }
+        queries
+    }
+
+    /// Queues an answer and sends as much as the client takes
+    pub fn send(&mut self, message: &[u8]) {
+        if self.closed.is_some() {
+            return;
+        }
+        if self.output.len() + 2 + message.len() > MAX_OUTPUT {
+            self.close(&format!("more than {} bytes of answers not taken", MAX_OUTPUT));
+            return;
+        }
+        if self.output.is_empty() {
+            self.last_sent = Instant::now();
+        }
+        self.output.extend(frame(message));
+        self.flush();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.is_some()
+    }
+
+    /// Gives up on the connection, it goes at the next `close_reason`
+    pub fn close(&mut self, reason: &str) {
+        if self.closed.is_none() {
+            self.closed = Some(reason.to_string());
+        }
+    }
+
+    pub fn flush(&mut self) {
+        while !self.output.is_empty() && self.closed.is_none() {
+            match self.stream.write(&self.output) {
+                Ok(0) => self.closed = Some("connection closed while sending".to_string()),
+                Ok(n) => {
+                    self.output.drain(..n);
+                    self.last_sent = Instant::now();
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => self.closed = Some(e.to_string()),
+            }
+        }
+    }
+
+    /// Why the connection should go, if it should.
+    ///
+    /// A client still waiting for answers keeps its connection until they are
+    /// sent, unless it stops taking them for longer than `idle_timeout`. Sending
+    /// more queries does not make up for not reading the answers.
+    pub fn close_reason(&self, idle_timeout: Duration, waiting: bool) -> Option<String> {
+        if let Some(reason) = &self.closed {
+            return Some(reason.clone());
+        }
+        if !self.output.is_empty() && self.last_sent.elapsed() >= idle_timeout {
+            return Some("not taking its answers".to_string());
+        }
+        if self.output.is_empty() && !waiting && self.last_received.elapsed().min(self.last_sent.elapsed()) >= idle_timeout {
+            return Some("idle".to_string());
+        }
+        if self.finished && !waiting && self.output.is_empty() {
+            return Some("closed by the client".to_string());
+        }
+        None
+    }
+}
@@ -0,0 +1,344 @@
+use crate::server::dns::message::*;
+use crate::server::reload::WatchedFile;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+
+// Lifetime of local answers
+pub const LOCAL_TTL: u32 = 300;
+// CNAMEs followed inside the zone before giving up on a chain
+const MAX_CNAME_CHAIN: usize = 8;
+// Meta type asking for every record of a name
+const TYPE_ANY: u16 = 255;
+
+/// Names lilap answers for itself, ahead of the portal and the upstream resolvers.
+///
+/// Records come from `dns_zone` in the config, then from a hosts-style file,
+/// then from the hostnames DHCP clients sent, below the local domain. Every A
+/// and AAAA record gets a PTR record for its address, unless one was given for
+/// the address already; the first name listed for an address is the one its
+/// PTR record points to.
+pub struct LocalZone {
+    config_records: Vec<Record>,
+    hosts_file: Option<WatchedFile>,
+    host_records: Vec<Record>,
+    // Where lease hostnames go, and the only source of names below it
+    domain: Option<Name>,
+    lease_names: HashMap<Ipv4Addr, Name>,
+    // Every record, PTRs included, by name
+    index: HashMap<Name, Vec<Record>>,
+}
+
+impl LocalZone {
+    /// Creates the zone from `dns_zone` entries and the hosts file, an empty path meaning there is none
+    pub fn new(hosts_path: &str, domain: Option<Name>) -> Self {
+        LocalZone {
+            config_records: Vec::new(),
+            hosts_file: Some(hosts_path).filter(|path| !path.is_empty()).map(WatchedFile::new),
+            host_records: Vec::new(),
+            domain,
+            lease_names: HashMap::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    /// Parses one `dns_zone` entry, such as `portal.lilap A 10.9.0.1` or `lilap MX 10 portal.lilap`
+    pub fn add_entry(&mut self, entry: &str) -> Result<(), String> {
+        let mut fields = entry.splitn(3, char::is_whitespace);
+        let (Some(name), Some(rtype), Some(data)) = (fields.next(), fields.next(), fields.next()) else {
+            return Err("expected a name, a type and data".to_string());
+        };
+        let rtype = parse_type(rtype).ok_or_else(|| format!("Unknown record type {}", rtype))?;
+        self.config_records.push(Self::record(name.parse()?, rtype, RData::from_text(rtype, data)?));
+        self.rebuild();
+        Ok(())
+    }
+
+    fn record(name: Name, rtype: u16, data: RData) -> Record {
+        Record {
+            name,
+            rtype,
+            class: CLASS_IN,
+            ttl: LOCAL_TTL,
+            data,
+        }
+    }
+
+    /// Parses the hosts file again if it changed, returning what happened for the log
+    pub fn reload(&mut self) -> Vec<String> {
+        let Some(file) = &mut self.hosts_file else {
+            return Vec::new();
+        };
+        let contents = match file.poll() {
+            None => return Vec::new(),
+            Some(Ok(contents)) => contents,
+            Some(Err(e)) => {
+                return vec![format!("Could not read hosts file {}, keeping {} records: {}", file.path(), self.host_records.len(), e)];
+            }
+        };
+
+        // Lines look like `<addr> <name> [<alias>...]`
+        let mut messages = Vec::new();
+        let mut records = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let mut fields = line.split('#').next().unwrap_or("").split_whitespace();
+            let Some(addr) = fields.next() else {
+                continue;
+            };
+            let data = match addr.parse::<IpAddr>() {
+                Ok(IpAddr::V4(addr)) => RData::A(addr),
+                Ok(IpAddr::V6(addr)) => RData::Aaaa(addr),
+                Err(_) => {
+                    messages.push(format!("Skipping line {} of {}: Invalid address {}", i + 1, file.path(), addr));
+                    continue;
+                }
+            };
+            let rtype = if matches!(data, RData::A(_)) { TYPE_A } else { TYPE_AAAA };
+            for name in fields {
+                match name.parse::<Name>() {
+                    Ok(name) => records.push(Self::record(name, rtype, data.clone())),
+                    Err(e) => messages.push(format!("Skipping {} on line {} of {}: {}", name, i + 1, file.path(), e)),
+                }
+            }
+        }
+        messages.push(format!("Loaded {} records from hosts file {}", records.len(), file.path()));
+        self.host_records = records;
+        self.rebuild();
+        messages
+    }
+
+    /// Makes `<hostname>.<domain>` resolve to a leased address, returning the name if it is new.
+    ///
+    /// Only the first label of the hostname is used. A name already taken by the
+    /// config or the hosts file is refused, one taken by another lease moves over.
+    pub fn add_lease(&mut self, addr: Ipv4Addr, hostname: &str) -> Result<Option<Name>, String> {
+        let Some(domain) = &self.domain else {
+            return Err("no local domain".to_string());
+        };
+        let label = hostname.split('.').next().unwrap_or("");
+        let name: Name = format!("{}.{}", label, domain).parse()?;
+        if self.config_records.iter().chain(&self.host_records).any(|record| record.name == name) {
+            return Err(format!("{} is already in the local zone", name));
+        }
+        if self.lease_names.get(&addr) == Some(&name) {
+            return Ok(None);
+        }
+        self.lease_names.retain(|_, known| *known != name);
+        self.lease_names.insert(addr, name.clone());
+        self.rebuild();
+        Ok(Some(name))
+    }
+
+    /// Forgets the name of a leased address, returning it if there was one
+    pub fn remove_lease(&mut self, addr: Ipv4Addr) -> Option<Name> {
+        let name = self.lease_names.remove(&addr)?;
+        self.rebuild();
+        Some(name)
+    }
+
+    /// Whether the name lies in the local domain, where names the zone does not know do not exist
+    pub fn is_local(&self, name: &Name) -> bool {
+        self.domain.as_ref().is_some_and(|domain| name.is_subdomain_of(domain))
+    }
+
+    fn rebuild(&mut self) {
+        let mut index: HashMap<Name, Vec<Record>> = HashMap::new();
+        let records = self.config_records.iter().chain(&self.host_records);
+        for record in records.clone() {
+            let known = index.entry(record.name.clone()).or_default();
+            if !known.iter().any(|existing| existing.rtype == record.rtype && existing.data == record.data) {
+                known.push(record.clone());
+            }
+        }
+        // The hosts file may have taken a lease name since it was added
+        let lease_records: Vec<Record> = self
+            .lease_names
+            .iter()
+            .filter(|(_, name)| !index.contains_key(*name))
+            .map(|(addr, name)| Self::record(name.clone(), TYPE_A, RData::A(*addr)))
+            .collect();
+        for record in &lease_records {
+            index.insert(record.name.clone(), vec![record.clone()]);
+        }
+        let records = records.chain(&lease_records);
+
+        for record in records {
+            let addr = match record.data {
+                RData::A(addr) => IpAddr::V4(addr),
+                RData::Aaaa(addr) => IpAddr::V6(addr),
+                _ => continue,
+            };
+            let reverse = Name::reverse(addr);
+            let known = index.entry(reverse.clone()).or_default();
+            if !known.iter().any(|existing| existing.rtype == TYPE_PTR) {
+                known.push(Self::record(reverse, TYPE_PTR, RData::Ptr(record.name.clone())));
+            }
+        }
+        self.index = index;
+    }
+
+    /// Answers for `name`, or None if the zone does not know the name at all.
+    ///
+    /// An empty answer means the name exists, just not with that type. CNAMEs
+    /// are followed as far as the zone goes.
+    pub fn lookup(&self, name: &Name, qtype: u16) -> Option<Vec<Record>> {
+        let Some(records) = self.index.get(name) else {
+            // The domain itself exists even when nothing was put there
+            return (self.domain.as_ref() == Some(name)).then(Vec::new);
+        };
+        let mut answers: Vec<Record> = records
+            .iter()
+            .filter(|record| record.rtype == qtype || qtype == TYPE_ANY)
+            .cloned()
+            .collect();
+
+        if answers.is_empty() && qtype != TYPE_CNAME {
+            if let Some(cname) = records.iter().find(|record| record.rtype == TYPE_CNAME) {
+                answers.push(cname.clone());
+                let mut target = cname.clone();
+                for _ in 0..MAX_CNAME_CHAIN {
+                    let RData::Cname(next) = &target.data else {
+                        break;
+                    };
+                    let Some(next_records) = self.index.get(next) else {
+                        break;
+                    };
+                    let found: Vec<Record> = next_records.iter().filter(|record| record.rtype == qtype).cloned().collect();
+                    if !found.is_empty() {
+                        answers.extend(found);
+                        break;
+                    }
+                    match next_records.iter().find(|record| record.rtype == TYPE_CNAME) {
+                        Some(next_cname) => {
+                            answers.push(next_cname.clone());
+                            target = next_cname.clone();
+                        }
+                        None => break,
+                    }
+                }
+            }
+        }
+        Some(answers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // A hosts file path of its own for every test, removed on drop
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("lilap-{}-{}", std::process::id(), name));
+            let _ = fs::remove_file(&path);
+            TempFile(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn name(s: &str) -> Name {
+        s.parse().unwrap()
+    }
+
+    fn data(zone: &LocalZone, name: &Name, qtype: u16) -> Vec<RData> {
+        zone.lookup(name, qtype).unwrap_or_default().into_iter().map(|record| record.data).collect()
+    }
+
+    #[test]
+    fn hosts_file() {
+        let hosts = TempFile::new("hosts");
+        fs::write(&hosts.0, "# printers\n10.9.0.5 printer printer.guest # by the door\n\nfd00::5 printer\nbogus name\n10.9.0.6 bad..name\n").unwrap();
+        let mut zone = LocalZone::new(hosts.path(), None);
+        let messages = zone.reload();
+        assert!(messages.iter().any(|message| message.starts_with("Skipping line 5")), "{:?}", messages);
+        assert!(messages.iter().any(|message| message.starts_with("Skipping bad..name on line 6")), "{:?}", messages);
+        assert_eq!(messages.last().unwrap(), &format!("Loaded 3 records from hosts file {}", hosts.path()));
+
+        assert_eq!(data(&zone, &name("printer"), TYPE_A), [RData::A(Ipv4Addr::new(10, 9, 0, 5))]);
+        assert_eq!(data(&zone, &name("printer"), TYPE_AAAA), [RData::Aaaa("fd00::5".parse().unwrap())]);
+        assert_eq!(data(&zone, &name("printer.guest"), TYPE_A), [RData::A(Ipv4Addr::new(10, 9, 0, 5))]);
+        assert_eq!(zone.reload(), Vec::<String>::new());
+
+        // Gone from the file, the records it held are kept
+        fs::remove_file(&hosts.0).unwrap();
+        assert!(zone.reload()[0].contains("keeping 3 records"));
+        assert!(zone.lookup(&name("printer"), TYPE_A).is_some());
+    }
+
+    #[test]
+    fn ptr_records() {
+        let mut zone = LocalZone::new("", None);
+        zone.add_entry("portal.lilap A 10.9.0.1").unwrap();
+        zone.add_entry("www.lilap A 10.9.0.1").unwrap();
+        zone.add_entry("portal.lilap AAAA fd00:1::1").unwrap();
+        zone.add_entry("router.lilap A 10.9.0.254").unwrap();
+        zone.add_entry("254.0.9.10.in-addr.arpa PTR gateway.lilap").unwrap();
+
+        // The first name listed for an address wins, a PTR given for it stays
+        assert_eq!(data(&zone, &name("1.0.9.10.in-addr.arpa"), TYPE_PTR), [RData::Ptr(name("portal.lilap"))]);
+        assert_eq!(data(&zone, &Name::reverse("fd00:1::1".parse().unwrap()), TYPE_PTR), [RData::Ptr(name("portal.lilap"))]);
+        assert_eq!(data(&zone, &name("254.0.9.10.in-addr.arpa"), TYPE_PTR), [RData::Ptr(name("gateway.lilap"))]);
+    }
+
+    #[test]
+    fn lookups_ignore_case() {
+        let mut zone = LocalZone::new("", Some(name("lilap")));
+        zone.add_entry("Portal.Lilap A 10.9.0.1").unwrap();
+        zone.add_entry("www.lilap CNAME PORTAL.lilap").unwrap();
+        assert_eq!(data(&zone, &name("PORTAL.LILAP"), TYPE_A), [RData::A(Ipv4Addr::new(10, 9, 0, 1))]);
+        assert_eq!(data(&zone, &name("WWW.lilap"), TYPE_A), [RData::Cname(name("portal.lilap")), RData::A(Ipv4Addr::new(10, 9, 0, 1))]);
+
+        // Names of the domain that the zone does not know do not exist, the domain itself does
+        assert!(zone.is_local(&name("Nothing.LILAP")));
+        assert_eq!(zone.lookup(&name("nothing.lilap"), TYPE_A), None);
+        assert_eq!(zone.lookup(&name("LILAP"), TYPE_A), Some(Vec::new()));
+        assert_eq!(zone.lookup(&name("portal.lilap"), TYPE_MX), Some(Vec::new()));
+    }
+
+    #[test]
+    fn lease_names() {
+        let hosts = TempFile::new("lease-hosts");
+        let mut zone = LocalZone::new(hosts.path(), Some(name("lan")));
+        zone.add_entry("printer.lan A 10.9.0.5").unwrap();
+        let laptop = Ipv4Addr::new(10, 9, 0, 100);
+        let phone = Ipv4Addr::new(10, 9, 0, 101);
+
+        assert_eq!(zone.add_lease(laptop, "Laptop.example.com"), Ok(Some(name("laptop.lan"))));
+        assert_eq!(zone.add_lease(laptop, "laptop"), Ok(None));
+        assert_eq!(data(&zone, &name("laptop.lan"), TYPE_A), [RData::A(laptop)]);
+        assert_eq!(data(&zone, &Name::reverse(IpAddr::V4(laptop)), TYPE_PTR), [RData::Ptr(name("laptop.lan"))]);
+        assert!(zone.add_lease(phone, "printer").is_err());
+
+        // Another lease taking the name moves it over
+        assert_eq!(zone.add_lease(phone, "laptop"), Ok(Some(name("laptop.lan"))));
+        assert_eq!(data(&zone, &name("laptop.lan"), TYPE_A), [RData::A(phone)]);
+        assert_eq!(zone.lookup(&Name::reverse(IpAddr::V4(laptop)), TYPE_PTR), None);
+        assert_eq!(zone.remove_lease(laptop), None);
+
+        // The hosts file overrides a lease name it takes later
+        fs::write(&hosts.0, "10.9.0.7 laptop.lan\n").unwrap();
+        zone.reload();
+        assert_eq!(data(&zone, &name("laptop.lan"), TYPE_A), [RData::A(Ipv4Addr::new(10, 9, 0, 7))]);
+        fs::write(&hosts.0, "").unwrap();
+        // Both writes may fall within one tick of the file system clock
+        fs::File::options().write(true).open(&hosts.0).unwrap().set_modified(std::time::UNIX_EPOCH).unwrap();
+        zone.reload();
+        assert_eq!(data(&zone, &name("laptop.lan"), TYPE_A), [RData::A(phone)]);
+
+        assert_eq!(zone.remove_lease(phone), Some(name("laptop.lan")));
+        assert_eq!(zone.lookup(&name("laptop.lan"), TYPE_A), None);
+        assert!(LocalZone::new("", None).add_lease(laptop, "laptop").is_err());
+    }
+}
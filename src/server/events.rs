@@ -0,0 +1,62 @@
+use crate::server::registry::Client;
+use once_cell::sync::Lazy;
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+
+static SUBSCRIBERS: Lazy<Mutex<Vec<Sender<LeaseEvent>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// A change in the DHCP lease table
+#[derive(Clone, Debug)]
+pub enum LeaseEvent {
+    /// A client got an address it did not hold before
+    Granted(Client),
+    /// A client extended the lease on the address it holds
+    Renewed(Client),
+    /// A client gave its address back
+    Released(Client),
+    /// A lease ran out without being renewed
+    Expired(Client),
+}
+
+impl LeaseEvent {
+    pub fn client(&self) -> &Client {
+        match self {
+            LeaseEvent::Granted(client)
+            | LeaseEvent::Renewed(client)
+            | LeaseEvent::Released(client)
+            | LeaseEvent::Expired(client) => client,
+        }
+    }
+
+    /// Whether the client no longer holds its address
+    pub fn is_end(&self) -> bool {
+        matches!(self, LeaseEvent::Released(_) | LeaseEvent::Expired(_))
+    }
+}
+
+impl fmt::Display for LeaseEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self {
+            LeaseEvent::Granted(_) => "granted",
+            LeaseEvent::Renewed(_) => "renewed",
+            LeaseEvent::Released(_) => "released",
+            LeaseEvent::Expired(_) => "expired",
+        };
+        let client = self.client();
+        write!(f, "Lease of {} for {} {}", client.addr, client, action)
+    }
+}
+
+/// Returns a channel receiving every lease event from now on.
+///
+/// Subscribers that drop their receiver are forgotten on the next event.
+pub fn subscribe() -> Receiver<LeaseEvent> {
+    let (tx, rx) = mpsc::channel();
+    SUBSCRIBERS.lock().unwrap().push(tx);
+    rx
+}
+
+pub fn publish(event: &LeaseEvent) {
+    SUBSCRIBERS.lock().unwrap().retain(|tx| tx.send(event.clone()).is_ok());
+}
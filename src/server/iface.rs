@@ -0,0 +1,138 @@
+use libc::{c_int, c_void};
+use std::ffi::CString;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::mem;
+use std::net::{Ipv6Addr, UdpSocket};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let octets: Vec<String> = self.0.iter().map(|byte| format!("{:02x}", byte)).collect();
+        write!(f, "{}", octets.join(":"))
+    }
+}
+
+impl FromStr for MacAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let octets: Vec<&str> = s.split([':', '-']).collect();
+        if octets.len() != 6 {
+            return Err(format!("Invalid MAC address: {}", s));
+        }
+        let mut mac = [0u8; 6];
+        for (byte, octet) in mac.iter_mut().zip(octets) {
+            *byte = u8::from_str_radix(octet, 16).map_err(|_| format!("Invalid MAC address: {}", s))?;
+        }
+        Ok(MacAddr(mac))
+    }
+}
+
+pub fn interface_index(name: &str) -> Result<i32> {
+    let cname = CString::new(name).map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid interface name"))?;
+    let index = unsafe { libc::if_nametoindex(cname.as_ptr()) };
+    if index == 0 {
+        return Err(Error::new(ErrorKind::NotFound, "Interface not found"));
+    }
+    Ok(index as i32)
+}
+
+pub fn interface_mac(name: &str) -> Result<MacAddr> {
+    let cname = CString::new(name).map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid interface name"))?;
+
+    // SIOCGIFHWADDR works on any socket, a throwaway UDP one does
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    let mut ifreq: libc::ifreq = unsafe { mem::zeroed() };
+    for (dst, src) in ifreq.ifr_name.iter_mut().zip(cname.as_bytes()) {
+        *dst = *src as libc::c_char;
+    }
+    if unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCGIFHWADDR, &mut ifreq) } < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let hwaddr = unsafe { ifreq.ifr_ifru.ifru_hwaddr };
+    let mut mac = [0u8; 6];
+    for (dst, src) in mac.iter_mut().zip(hwaddr.sa_data.iter()) {
+        *dst = *src as u8;
+    }
+    Ok(MacAddr(mac))
+}
+
+/// Hardware address the kernel's neighbour table holds for `addr`, learnt through neighbour discovery
+pub fn neighbour_mac(addr: Ipv6Addr) -> Result<Option<MacAddr>> {
+    let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE) };
+    if fd < 0 {
+        return Err(Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // Dump the IPv6 neighbours: nlmsghdr followed by an ndmsg asking for AF_INET6
+    let mut request = Vec::with_capacity(NLMSG_HDR_LEN + NDMSG_LEN);
+    request.extend(((NLMSG_HDR_LEN + NDMSG_LEN) as u32).to_ne_bytes());
+    request.extend(libc::RTM_GETNEIGH.to_ne_bytes());
+    request.extend(((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+    request.extend(1u32.to_ne_bytes());
+    request.extend(0u32.to_ne_bytes());
+    request.push(libc::AF_INET6 as u8);
+    request.extend([0; NDMSG_LEN - 1]);
+    if unsafe { libc::send(fd.as_raw_fd(), request.as_ptr() as *const c_void, request.len(), 0) } < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let mut found = None;
+    let mut buffer = vec![0u8; 32 * 1024];
+    loop {
+        let len = unsafe { libc::recv(fd.as_raw_fd(), buffer.as_mut_ptr() as *mut c_void, buffer.len(), 0) };
+        if len < 0 {
+            return Err(Error::last_os_error());
+        }
+        let mut messages = &buffer[..len as usize];
+        while messages.len() >= NLMSG_HDR_LEN {
+            let msg_len = u32::from_ne_bytes([messages[0], messages[1], messages[2], messages[3]]) as usize;
+            let msg_type = u16::from_ne_bytes([messages[4], messages[5]]);
+            if msg_len < NLMSG_HDR_LEN || msg_len > messages.len() {
+                return Err(Error::new(ErrorKind::InvalidData, "Malformed netlink message"));
+            }
+            match msg_type as c_int {
+                libc::NLMSG_DONE => return Ok(found),
+                libc::NLMSG_ERROR => return Err(Error::other("Neighbour table dump failed")),
+                _ if msg_type == libc::RTM_NEWNEIGH && found.is_none() => found = parse_neighbour(&messages[NLMSG_HDR_LEN..msg_len], addr),
+                _ => {}
+            }
+            messages = &messages[align(msg_len).min(messages.len())..];
+        }
+    }
+}
+
+const NLMSG_HDR_LEN: usize = 16;
+const NDMSG_LEN: usize = 12;
+const NDA_DST: u16 = 1;
+const NDA_LLADDR: u16 = 2;
+
+// Netlink messages and attributes start on 4 byte boundaries
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+// The link-layer address of an RTM_NEWNEIGH payload, if the entry is for `addr`
+fn parse_neighbour(payload: &[u8], addr: Ipv6Addr) -> Option<MacAddr> {
+    let mut attrs = payload.get(NDMSG_LEN..)?;
+    let (mut dst, mut lladdr) = (None, None);
+    while attrs.len() >= 4 {
+        let len = u16::from_ne_bytes([attrs[0], attrs[1]]) as usize;
+        let kind = u16::from_ne_bytes([attrs[2], attrs[3]]);
+        let data = attrs.get(4..len)?;
+        match (kind, data.len()) {
+            (NDA_DST, 16) => dst = <[u8; 16]>::try_from(data).ok().map(Ipv6Addr::from),
+            (NDA_LLADDR, 6) => lladdr = <[u8; 6]>::try_from(data).ok().map(MacAddr),
+            _ => {}
+        }
+        attrs = &attrs[align(len).min(attrs.len())..];
+    }
+    lladdr.filter(|_| dst == Some(addr))
+}
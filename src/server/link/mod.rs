@@ -2,11 +2,8 @@ use crate::{lock, receiver, server::*, server_state};
 use confee::conf::*;
 use std::net::IpAddr;
 use neli::{
-    consts::{genl::*, nl::*, socket::*},
-    genl::{Genlmsghdr, Nlattr, GenlmsghdrBuilder},
-    nl::{Nlmsghdr, NlPayload, NlmsghdrBuilder},
+    consts::socket::*,
     router::synchronous::NlRouter,
-    types::{Buffer, GenlBuffer},
     utils::Groups,
 };
 use std::ffi::CString;
@@ -16,8 +13,10 @@ use std::time::Duration;
 use std::sync::mpsc;
 
 
+#[allow(dead_code)]
 const NL80211_CMD_NEW_INTERFACE: u8 = 4;
 
+#[allow(dead_code)]
 pub struct Link {
     addr: IpAddr,
     parent_iface_name: String,
@@ -40,7 +39,7 @@ impl Server for Link {
     }
 
     fn mainloop(&self) {
-        let mut socket = NlRouter::connect(NlFamily::Generic, None, Groups::empty());
+        let _socket = NlRouter::connect(NlFamily::Generic, None, Groups::empty());
 
         let parent_iface = self.parent_iface_name.as_str();
         match self.get_interface_index(parent_iface) {
@@ -50,7 +49,7 @@ impl Server for Link {
     
         loop {
             lock!(receiver!(self), rx => {
-                if let Ok(_) = rx.try_recv() {
+                if rx.try_recv().is_ok() {
                     self.log("Stop signal received. Shutting down.");
                     break;
                 }
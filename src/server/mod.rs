@@ -2,7 +2,14 @@ pub mod common;
 pub mod web;
 pub mod dns;
 pub mod dhcp;
+pub mod dhcp6;
 pub mod link;
+pub mod ra;
+pub mod events;
+pub mod iface;
+pub mod portal;
+pub mod registry;
+pub mod reload;
 use confee::conf::*;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{Arc, Mutex};
@@ -27,6 +34,7 @@ macro_rules! receiver {
 #[macro_export]
 macro_rules! try_lock_or_panic {
     ($lock:expr, $var:ident => $($body:tt)*) => {{
+        #[allow(unused_mut)]
         let mut $var = $lock.try_lock().expect("Failed to acquire lock immediately");
         $($body)*
     }};
@@ -35,6 +43,7 @@ macro_rules! try_lock_or_panic {
 #[macro_export]
 macro_rules! lock {
     ($lock:expr, $var:ident => $($body:tt)*) => {{
+        #[allow(unused_mut)]
         let mut $var = $lock.lock().unwrap();
         $($body)*
     }};
@@ -61,10 +70,12 @@ pub struct ServerState {
 
 pub trait HasServerState {
     fn get_state(&self) -> &ServerState;
+    #[allow(dead_code)]
     fn get_state_mut(&mut self) -> &mut ServerState;
 }
 pub trait HasStateField {
     fn state(&self) -> &ServerState;
+    #[allow(dead_code)]
     fn state_mut(&mut self) -> &mut ServerState;
 }
 
@@ -73,7 +84,7 @@ where
     T: HasStateField,
 {
     fn get_state(&self) -> &ServerState {
-        &self.state()
+        self.state()
     }
 
     fn get_state_mut(&mut self) -> &mut ServerState {
@@ -0,0 +1,14 @@
+use confee::conf::*;
+use std::net::Ipv4Addr;
+
+/// RFC 8910 portal URI, pointing at our own Web server unless configured otherwise
+pub fn captive_portal_uri(conf: &Conf, addr: Ipv4Addr) -> String {
+    let uri: String = conf.get("dhcp_captive_portal_uri").unwrap();
+    if !uri.is_empty() {
+        return uri;
+    }
+    match conf.get::<u16>("web_port").unwrap() {
+        80 => format!("http://{}/", addr),
+        port => format!("http://{}:{}/", addr, port),
+    }
+}
@@ -0,0 +1,115 @@
+use crate::server::iface::MacAddr;
+use std::net::Ipv6Addr;
+
+pub const ND_ROUTER_SOLICIT: u8 = 133;
+pub const ND_ROUTER_ADVERT: u8 = 134;
+
+// Neighbor discovery option types
+const OPT_SOURCE_LINK_ADDR: u8 = 1;
+const OPT_PREFIX_INFO: u8 = 3;
+const OPT_RDNSS: u8 = 25;
+const OPT_CAPTIVE_PORTAL: u8 = 37;
+
+// Advertisement flags: addresses come from DHCPv6, and so does other configuration
+const FLAG_MANAGED: u8 = 0x80;
+const FLAG_OTHER: u8 = 0x40;
+
+// Prefix information flags: on-link, and usable for SLAAC
+const PREFIX_ON_LINK: u8 = 0x80;
+const PREFIX_AUTONOMOUS: u8 = 0x40;
+
+/// Prefix handed out for SLAAC, written as `<addr>/<len>` in the config
+#[derive(Clone, Copy, Debug)]
+pub struct Prefix {
+    pub addr: Ipv6Addr,
+    pub len: u8,
+}
+
+impl std::str::FromStr for Prefix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid prefix: {}", s);
+        let (addr, len) = s.split_once('/').ok_or_else(invalid)?;
+        let addr: Ipv6Addr = addr.parse().map_err(|_| invalid())?;
+        let len: u8 = len.parse().map_err(|_| invalid())?;
+        if len > 128 {
+            return Err(invalid());
+        }
+        // Keep only the prefix bits
+        let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+        Ok(Prefix {
+            addr: Ipv6Addr::from(u128::from(addr) & mask),
+            len,
+        })
+    }
+}
+
+impl std::fmt::Display for Prefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+/// Contents of a router advertisement (RFC 4861 section 4.2)
+pub struct RouterAdvert {
+    pub router_lifetime: u16,
+    /// Whether clients should ask the DHCPv6 server, setting the M and O flags
+    pub managed: bool,
+    pub mac: Option<MacAddr>,
+    pub prefix: Prefix,
+    pub valid_lifetime: u32,
+    pub preferred_lifetime: u32,
+    /// RFC 8106 recursive DNS servers
+    pub dns_servers: Vec<Ipv6Addr>,
+    pub dns_lifetime: u32,
+    /// RFC 8910 captive portal URI
+    pub captive_portal_uri: String,
+}
+
+impl RouterAdvert {
+    /// Serializes the ICMPv6 message, leaving the checksum to the kernel
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut message = vec![ND_ROUTER_ADVERT, 0, 0, 0];
+        message.push(64); // Current hop limit suggested to hosts
+        message.push(if self.managed { FLAG_MANAGED | FLAG_OTHER } else { 0 });
+        message.extend(&self.router_lifetime.to_be_bytes());
+        message.extend(&0u32.to_be_bytes()); // Reachable time, unspecified
+        message.extend(&0u32.to_be_bytes()); // Retransmission timer, unspecified
+
+        if let Some(mac) = &self.mac {
+            Self::push_option(&mut message, OPT_SOURCE_LINK_ADDR, &mac.0);
+        }
+
+        let mut prefix_info = vec![self.prefix.len, PREFIX_ON_LINK | PREFIX_AUTONOMOUS];
+        prefix_info.extend(&self.valid_lifetime.to_be_bytes());
+        prefix_info.extend(&self.preferred_lifetime.to_be_bytes());
+        prefix_info.extend(&[0; 4]);
+        prefix_info.extend(&self.prefix.addr.octets());
+        Self::push_option(&mut message, OPT_PREFIX_INFO, &prefix_info);
+
+        if !self.dns_servers.is_empty() {
+            let mut rdnss = vec![0, 0];
+            rdnss.extend(&self.dns_lifetime.to_be_bytes());
+            for server in &self.dns_servers {
+                rdnss.extend(&server.octets());
+            }
+            Self::push_option(&mut message, OPT_RDNSS, &rdnss);
+        }
+
+        if !self.captive_portal_uri.is_empty() {
+            Self::push_option(&mut message, OPT_CAPTIVE_PORTAL, self.captive_portal_uri.as_bytes());
+        }
+
+        message
+    }
+
+    // Options are padded with zeros to a multiple of 8 bytes, their length counts in units of 8 bytes
+    fn push_option(message: &mut Vec<u8>, kind: u8, data: &[u8]) {
+        let len = (data.len() + 2).div_ceil(8);
+        message.push(kind);
+        message.push(len as u8);
+        message.extend(data);
+        message.resize(message.len() + len * 8 - data.len() - 2, 0);
+    }
+}
@@ -0,0 +1,199 @@
+pub mod advert;
+pub mod socket;
+
+use crate::server::{iface, portal};
+use crate::{lock, receiver, server::*, server_state};
+use advert::{Prefix, RouterAdvert, ND_ROUTER_SOLICIT};
+use confee::conf::*;
+use rand::Rng;
+use socket::{Icmp6Socket, ND_HOP_LIMIT};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+// RFC 4861 section 10: the first few advertisements go out faster, and solicited ones are rate limited
+const MAX_INITIAL_RTR_ADVERTISEMENTS: u32 = 3;
+const MAX_INITIAL_RTR_ADVERT_INTERVAL: Duration = Duration::from_secs(16);
+const MIN_DELAY_BETWEEN_RAS: Duration = Duration::from_secs(3);
+
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+/// Router advertisement sender, so dual-stack clients configure an address
+/// with SLAAC and learn about our DNS server and captive portal over IPv6
+pub struct Ra {
+    iface: String,
+    prefix: Option<Prefix>,
+    dns_servers: Vec<Ipv6Addr>,
+    captive_portal_uri: String,
+    // Whether DHCPv6 hands out addresses next to SLAAC
+    managed: bool,
+    interval: Duration,
+    router_lifetime: u16,
+    valid_lifetime: u32,
+    preferred_lifetime: u32,
+    pub state: ServerState,
+}
+
+impl Server for Ra {
+    fn create(conf: &Conf) -> Self {
+        let link_addr: Ipv4Addr = conf.get("link_addr").unwrap();
+        let mut ra = Ra {
+            iface: conf.get("link_iface").unwrap(),
+            prefix: None,
+            dns_servers: conf.get::<Ipv6Addr>("link_addr6").into_iter().collect(),
+            captive_portal_uri: portal::captive_portal_uri(conf, link_addr),
+            // Same check Dhcp6 makes before serving its pool
+            managed: matches!(
+                (conf.get::<Ipv6Addr>("dhcp6_pool_start"), conf.get::<Ipv6Addr>("dhcp6_pool_end")),
+                (Some(start), Some(end)) if start <= end
+            ),
+            interval: Duration::from_secs(conf.get("ra_interval").unwrap()),
+            router_lifetime: conf.get("ra_router_lifetime").unwrap(),
+            valid_lifetime: conf.get("ra_valid_lifetime").unwrap(),
+            preferred_lifetime: conf.get("ra_preferred_lifetime").unwrap(),
+            state: server_state!(),
+        };
+        ra.state.prefix = String::from("ra");
+
+        let prefix = &conf["ra_prefix"];
+        if !prefix.is_empty() {
+            match prefix.parse::<Prefix>() {
+                Ok(prefix) if prefix.len != 64 => ra.log(&format!("Ignoring ra_prefix, SLAAC needs a /64, not {}", prefix)),
+                Ok(prefix) => ra.prefix = Some(prefix),
+                Err(e) => ra.log(&format!("Ignoring ra_prefix: {}", e)),
+            }
+        }
+        if ra.managed {
+            ra.log("Pointing clients at DHCPv6 with the managed and other configuration flags");
+        }
+        if ra.dns_servers.is_empty() {
+            ra.log("No link_addr6, advertising no DNS server");
+        }
+        ra
+    }
+
+    fn mainloop(&self) {
+        let socket = match self.prefix.map(|prefix| (prefix, self.open_socket())) {
+            Some((prefix, Ok(socket))) => {
+                self.log(&format!("Advertising {} on {}", prefix, self.iface));
+                Some((prefix, socket))
+            }
+            Some((_, Err(e))) => {
+                self.log(&format!("Could not open ICMPv6 socket on {}: {}", self.iface, e));
+                None
+            }
+            None => {
+                self.log("No ra_prefix, not advertising");
+                None
+            }
+        };
+
+        let mut sent = 0;
+        let mut last_sent: Option<Instant> = None;
+        let mut next_unsolicited = Instant::now();
+        let mut solicited: Option<Instant> = None;
+        loop {
+            lock!(receiver!(self), rx => {
+                if rx.try_recv().is_ok() {
+                    self.log("Stop signal received. Shutting down.");
+                    break;
+                }
+            });
+
+            if let Some((prefix, socket)) = &socket {
+                if self.solicited(socket) && solicited.is_none() {
+                    // Answer right away unless we just advertised
+                    let earliest = last_sent.map_or(Instant::now(), |last| last + MIN_DELAY_BETWEEN_RAS);
+                    solicited = Some(earliest.max(Instant::now()));
+                }
+
+                let now = Instant::now();
+                if now >= next_unsolicited || solicited.is_some_and(|at| now >= at) {
+                    self.advertise(socket, *prefix);
+                    sent += 1;
+                    last_sent = Some(now);
+                    solicited = None;
+                    next_unsolicited = now + self.next_interval(sent);
+                }
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // Tell clients we are gone, RFC 4861 section 6.2.5
+        if let Some((prefix, socket)) = &socket {
+            self.send(socket, &self.create_advert(*prefix, 0));
+        }
+        self.log("Stopped");
+    }
+}
+
+impl Ra {
+    fn open_socket(&self) -> io::Result<Icmp6Socket> {
+        let ifindex = iface::interface_index(&self.iface)? as u32;
+        let socket = Icmp6Socket::open(&self.iface, ifindex)?;
+        socket.join(ALL_ROUTERS)?;
+        Ok(socket)
+    }
+
+    // Unsolicited advertisements go out at random intervals between a third of the interval and the interval
+    fn next_interval(&self, sent: u32) -> Duration {
+        let max = if sent < MAX_INITIAL_RTR_ADVERTISEMENTS {
+            self.interval.min(MAX_INITIAL_RTR_ADVERT_INTERVAL)
+        } else {
+            self.interval
+        };
+        let max_ms = max.as_millis().max(1) as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(max_ms / 3..=max_ms))
+    }
+
+    // Drains the socket, returns whether any valid router solicitation was among what came in
+    fn solicited(&self, socket: &Icmp6Socket) -> bool {
+        let mut solicited = false;
+        let mut buffer = [0u8; 1500];
+        loop {
+            match socket.recv(&mut buffer) {
+                Ok((n, src, hop_limit)) => {
+                    if n >= 8 && buffer[0] == ND_ROUTER_SOLICIT && buffer[1] == 0 && hop_limit == Some(ND_HOP_LIMIT) {
+                        self.log(&format!("Router solicitation from {}", src.ip()));
+                        solicited = true;
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return solicited,
+                Err(e) => {
+                    self.log(&format!("Error receiving solicitation: {}", e));
+                    return solicited;
+                }
+            }
+        }
+    }
+
+    fn create_advert(&self, prefix: Prefix, router_lifetime: u16) -> RouterAdvert {
+        RouterAdvert {
+            router_lifetime,
+            managed: self.managed,
+            mac: iface::interface_mac(&self.iface).ok(),
+            prefix,
+            valid_lifetime: self.valid_lifetime,
+            preferred_lifetime: self.preferred_lifetime,
+            dns_servers: self.dns_servers.clone(),
+            // RFC 8106 recommends at least three times the advertisement interval
+            dns_lifetime: (self.interval.as_secs() * 3) as u32,
+            captive_portal_uri: self.captive_portal_uri.clone(),
+        }
+    }
+
+    fn advertise(&self, socket: &Icmp6Socket, prefix: Prefix) {
+        self.send(socket, &self.create_advert(prefix, self.router_lifetime));
+    }
+
+    fn send(&self, socket: &Icmp6Socket, advert: &RouterAdvert) {
+        let message = advert.serialize();
+        match socket.send_to(&message, ALL_NODES) {
+            Ok(sent_bytes) => self.log(&format!("Sent {} byte router advertisement to {}", sent_bytes, ALL_NODES)),
+            Err(e) => self.log(&format!("Failed to send router advertisement: {}", e)),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use libc::{c_int, c_void, sockaddr, sockaddr_in6, socklen_t};
+use std::ffi::CString;
+use std::io::{Error, ErrorKind, Result};
+use std::mem;
+use std::net::{Ipv6Addr, SocketAddrV6};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+// Neighbor discovery messages must arrive with the hop limit they were sent with, RFC 4861 section 6.1
+pub const ND_HOP_LIMIT: c_int = 255;
+// Room for a single IPV6_HOPLIMIT control message
+const HOPLIMIT_CMSG_SPACE: usize = 64;
+
+/// Raw ICMPv6 socket restricted to one interface, the kernel fills in the checksums
+pub struct Icmp6Socket {
+    fd: OwnedFd,
+    ifindex: u32,
+}
+
+impl Icmp6Socket {
+    pub fn open(iface: &str, ifindex: u32) -> Result<Self> {
+        let fd = unsafe { libc::socket(libc::AF_INET6, libc::SOCK_RAW | libc::SOCK_NONBLOCK, libc::IPPROTO_ICMPV6) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        let socket = Icmp6Socket {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            ifindex,
+        };
+
+        let name = CString::new(iface).map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid interface name"))?;
+        socket.set_option(libc::SOL_SOCKET, libc::SO_BINDTODEVICE, name.as_bytes_with_nul())?;
+        socket.set_int(libc::IPPROTO_IPV6, libc::IPV6_MULTICAST_HOPS, ND_HOP_LIMIT)?;
+        socket.set_int(libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS, ND_HOP_LIMIT)?;
+        socket.set_int(libc::IPPROTO_IPV6, libc::IPV6_MULTICAST_LOOP, 0)?;
+        socket.set_int(libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1)?;
+        Ok(socket)
+    }
+
+    fn set_int(&self, level: c_int, name: c_int, value: c_int) -> Result<()> {
+        self.set_option(level, name, &value.to_ne_bytes())
+    }
+
+    fn set_option(&self, level: c_int, name: c_int, value: &[u8]) -> Result<()> {
+        let ret = unsafe {
+            libc::setsockopt(self.fd.as_raw_fd(), level, name, value.as_ptr() as *const c_void, value.len() as socklen_t)
+        };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Joins a multicast group on the socket's interface
+    pub fn join(&self, group: Ipv6Addr) -> Result<()> {
+        let mreq = libc::ipv6_mreq {
+            ipv6mr_multiaddr: libc::in6_addr { s6_addr: group.octets() },
+            ipv6mr_interface: self.ifindex,
+        };
+        let ret = unsafe {
+            libc::setsockopt(
+                self.fd.as_raw_fd(),
+                libc::IPPROTO_IPV6,
+                libc::IPV6_ADD_MEMBERSHIP,
+                &mreq as *const libc::ipv6_mreq as *const c_void,
+                mem::size_of::<libc::ipv6_mreq>() as socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Receives a message along with its sender and the hop limit it arrived with
+    pub fn recv(&self, buffer: &mut [u8]) -> Result<(usize, SocketAddrV6, Option<c_int>)> {
+        let mut src: sockaddr_in6 = unsafe { mem::zeroed() };
+        let mut iov = libc::iovec {
+            iov_base: buffer.as_mut_ptr() as *mut c_void,
+            iov_len: buffer.len(),
+        };
+        let mut control = [0u8; HOPLIMIT_CMSG_SPACE];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = &mut src as *mut sockaddr_in6 as *mut c_void;
+        msg.msg_namelen = mem::size_of::<sockaddr_in6>() as socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = control.len();
+
+        let len = unsafe { libc::recvmsg(self.fd.as_raw_fd(), &mut msg, 0) };
+        if len < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let mut hop_limit = None;
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        while !cmsg.is_null() {
+            let header = unsafe { &*cmsg };
+            if header.cmsg_level == libc::IPPROTO_IPV6 && header.cmsg_type == libc::IPV6_HOPLIMIT {
+                hop_limit = Some(unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const c_int) });
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+        }
+
+        let addr = SocketAddrV6::new(Ipv6Addr::from(src.sin6_addr.s6_addr), 0, 0, src.sin6_scope_id);
+        Ok((len as usize, addr, hop_limit))
+    }
+
+    /// Sends a message to `dst` out of the socket's interface
+    pub fn send_to(&self, message: &[u8], dst: Ipv6Addr) -> Result<usize> {
+        let mut addr: sockaddr_in6 = unsafe { mem::zeroed() };
+        addr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+        addr.sin6_addr.s6_addr = dst.octets();
+        addr.sin6_scope_id = self.ifindex;
+        let sent = unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                message.as_ptr() as *const c_void,
+                message.len(),
+                0,
+                &addr as *const sockaddr_in6 as *const sockaddr,
+                mem::size_of::<sockaddr_in6>() as socklen_t,
+            )
+        };
+        if sent < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(sent as usize)
+    }
+}
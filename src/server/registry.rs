@@ -0,0 +1,125 @@
+use crate::server::dhcp::lease::{ClientInfo, Lease};
+use crate::server::iface::{self, MacAddr};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Mutex;
+
+static CLIENTS: Lazy<Mutex<HashMap<MacAddr, Client>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A client holding a lease on the AP, as the DHCP server last saw it.
+///
+/// The DHCP server keeps the registry up to date, the other servers look
+/// clients up in it by the address they talk to them from. The portal
+/// session lives here too, and ends with the lease.
+#[derive(Clone, Debug)]
+pub struct Client {
+    pub mac: MacAddr,
+    pub addr: Ipv4Addr,
+    pub info: ClientInfo,
+    /// Whether the client passed the captive portal
+    pub authenticated: bool,
+}
+
+impl From<&Lease> for Client {
+    fn from(lease: &Lease) -> Self {
+        Client {
+            mac: lease.mac,
+            addr: lease.addr,
+            info: lease.info.clone(),
+            authenticated: false,
+        }
+    }
+}
+
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.info.label(&self.mac))
+    }
+}
+
+/// Adds the client, or updates it if its MAC is already known.
+///
+/// A client that keeps its address keeps its portal session, one that moved
+/// to another address has to pass the portal again.
+pub fn register(client: Client) {
+    let mut clients = CLIENTS.lock().unwrap();
+    let authenticated = clients.get(&client.mac).is_some_and(|known| known.addr == client.addr && known.authenticated);
+    clients.insert(
+        client.mac,
+        Client {
+            authenticated: client.authenticated || authenticated,
+            ..client
+        },
+    );
+}
+
+/// Drops the client, unless it has moved on to another address in the meantime
+pub fn unregister(mac: &MacAddr, addr: Ipv4Addr) {
+    let mut clients = CLIENTS.lock().unwrap();
+    if clients.get(mac).is_some_and(|client| client.addr == addr) {
+        clients.remove(mac);
+    }
+}
+
+/// Every client holding a lease
+pub fn clients() -> Vec<Client> {
+    CLIENTS.lock().unwrap().values().cloned().collect()
+}
+
+/// The client talking from `addr`.
+///
+/// Leases are IPv4 only, so an IPv6 peer is matched by the MAC the kernel's
+/// neighbour table holds for it.
+pub fn by_addr(addr: IpAddr) -> Option<Client> {
+    let key = ClientKey::of(addr)?;
+    CLIENTS.lock().unwrap().values().find(|client| key.matches(client)).cloned()
+}
+
+/// Lets the client at `addr` through the portal, returns it unless it holds no lease
+pub fn authenticate(addr: IpAddr) -> Option<Client> {
+    let key = ClientKey::of(addr)?;
+    let mut clients = CLIENTS.lock().unwrap();
+    let client = clients.values_mut().find(|client| key.matches(client))?;
+    client.authenticated = true;
+    Some(client.clone())
+}
+
+pub fn is_authenticated(addr: IpAddr) -> bool {
+    by_addr(addr).is_some_and(|client| client.authenticated)
+}
+
+// What identifies the client behind a peer address
+enum ClientKey {
+    Addr(Ipv4Addr),
+    Mac(MacAddr),
+}
+
+impl ClientKey {
+    // Looked up before taking the registry lock, the neighbour table is a netlink round trip away
+    fn of(addr: IpAddr) -> Option<Self> {
+        match addr {
+            IpAddr::V4(addr) => Some(ClientKey::Addr(addr)),
+            IpAddr::V6(addr) => match addr.to_ipv4_mapped() {
+                Some(addr) => Some(ClientKey::Addr(addr)),
+                None => iface::neighbour_mac(addr).ok().flatten().map(ClientKey::Mac),
+            },
+        }
+    }
+
+    fn matches(&self, client: &Client) -> bool {
+        match self {
+            ClientKey::Addr(addr) => client.addr == *addr,
+            ClientKey::Mac(mac) => client.mac == *mac,
+        }
+    }
+}
+
+/// Describes a peer for logs, by name and MAC if it holds a lease
+pub fn describe(addr: IpAddr) -> String {
+    match by_addr(addr) {
+        Some(client) => format!("{} at {}", client, addr),
+        None => addr.to_string(),
+    }
+}
@@ -0,0 +1,35 @@
+use std::fs;
+use std::io;
+use std::time::SystemTime;
+
+/// A file that is read again whenever its modification time changes
+pub struct WatchedFile {
+    path: String,
+    modified: Option<SystemTime>,
+    checked: bool,
+}
+
+impl WatchedFile {
+    pub fn new(path: &str) -> Self {
+        WatchedFile {
+            path: path.to_string(),
+            modified: None,
+            checked: false,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the contents on the first call and whenever the file changed, appeared or vanished since the last one
+    pub fn poll(&mut self) -> Option<io::Result<String>> {
+        let modified = fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok();
+        if self.checked && modified == self.modified {
+            return None;
+        }
+        self.checked = true;
+        self.modified = modified;
+        Some(fs::read_to_string(&self.path))
+    }
+}
@@ -7,6 +7,9 @@ use std::time::Duration;
 use std::io::{self, Write, Read};
 use std::fs;
 
+// Visiting this lets the client through the portal
+const ACCEPT_PATH: &str = "/accept";
+
 pub struct Web {
     dir: String,
     addr: IpAddr,
@@ -28,12 +31,12 @@ impl Server for Web {
 
     fn mainloop(&self) {
         let socket_addr = SocketAddr::new(self.addr, self.port);
-        let listener = TcpListener::bind(socket_addr).expect(&format!("{}: Could not bind to address", self.state.prefix));
-        listener.set_nonblocking(true).expect(&format!("{}: Failed to set non-blocking", self.state.prefix));
+        let listener = TcpListener::bind(socket_addr).unwrap_or_else(|_| panic!("{}: Could not bind to address", self.state.prefix));
+        listener.set_nonblocking(true).unwrap_or_else(|_| panic!("{}: Failed to set non-blocking", self.state.prefix));
 
         loop {
             lock!(receiver!(self), rx => {
-                if let Ok(_) = rx.try_recv() {
+                if rx.try_recv().is_ok() {
                     self.log("Stop signal received. Shutting down.");
                     break;
                 }
@@ -41,8 +44,8 @@ impl Server for Web {
 
             match listener.accept() {
                 Ok((ref mut stream, addr)) => {
-                    self.log(&format!("New connection from {}", addr));
-                    self.handle_connection(stream);
+                    self.log(&format!("New connection from {}", registry::describe(addr.ip())));
+                    self.handle_connection(stream, addr.ip());
                     self.log("Waiting for connections...");
                 }
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
@@ -61,16 +64,10 @@ impl Server for Web {
 }
 
 impl Web {
-    fn handle_connection(&self, stream: &mut TcpStream) {
+    fn handle_connection(&self, stream: &mut TcpStream, peer: IpAddr) {
         let mut buffer = [0u8; 4096];
-        match self.read_from_stream(stream, &mut buffer) {
-            Ok(_) => {
-                match self.send_response(stream, &mut buffer) {
-                    Ok(_) => {}
-                    Err(_) => {}
-                }
-            }
-            Err(_) => {}
+        if self.read_from_stream(stream, &mut buffer).is_ok() {
+            let _ = self.send_response(stream, &buffer, peer);
         }
     }
 
@@ -92,7 +89,7 @@ impl Web {
         }
     }
 
-    fn send_response(&self, stream: &mut TcpStream, buffer: &[u8]) -> Result<(), io::Error> {
+    fn send_response(&self, stream: &mut TcpStream, buffer: &[u8], peer: IpAddr) -> Result<(), io::Error> {
         let request = String::from_utf8_lossy(buffer);
 
         let request_line = request.lines().next().unwrap_or("");
@@ -104,7 +101,11 @@ impl Web {
         self.log(&format!("HTTP Request Method: {}", method));
         self.log(&format!("Requested Path: {}", path));
 
-        let response = self.handle_path(path);
+        let response = if path.split('?').next() == Some(ACCEPT_PATH) {
+            self.accept(peer)
+        } else {
+            self.handle_path(path)
+        };
 
         stream.write_all(response.as_bytes())?;
         stream.flush()?;
@@ -112,6 +113,20 @@ impl Web {
         Ok(())
     }
 
+    // From now on the client's DNS queries are answered by the upstream resolvers instead of the portal
+    fn accept(&self, peer: IpAddr) -> String {
+        match registry::authenticate(peer) {
+            Some(client) => {
+                self.log(&format!("{} at {} passed the portal", client, peer));
+                self.build_response(200, "You are online.")
+            }
+            None => {
+                self.log(&format!("{} holds no lease, not letting it through", peer));
+                self.build_response(403, "Your address holds no lease.")
+            }
+        }
+    }
+
     fn handle_path(&self, path: &str) -> String {
         if path.contains("..") {
             self.log("Detected directory traversal attempt.");
@@ -135,6 +150,7 @@ impl Web {
             200 => "OK",
             404 => "Not Found",
             400 => "Bad Request",
+            403 => "Forbidden",
             _ => "Unknown",
         };
         self.log(&format!("Response: {} {}", status_code, status_text));